@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+use bevy_physics_weekend::{
+    colliders::{ColliderBox, ColliderSphere},
+    constraints::TargetFollow,
+    primitives::{Body, RigidBody},
+};
+
+use super::CameraController;
+
+pub struct DragPlugin;
+
+impl Plugin for DragPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup).add_system(drag_system);
+    }
+}
+
+fn setup() {
+    info!("Hold left mouse to drag bodies");
+}
+
+/// The body being dragged, how far along the mouse ray it was grabbed
+#[derive(Component)]
+struct Drag {
+    distance: f32,
+}
+
+// Ray from the camera through the cursor, the camera uses a reverse infinite projection so ndc z
+// of 1 is the near plane and it gets further away as z goes to 0
+fn cursor_ray(
+    windows: &Windows,
+    camera: &Camera,
+    transform: &GlobalTransform,
+) -> Option<(Vec3, Vec3)> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    let size = Vec2::new(window.width(), window.height());
+    let ndc = cursor / size * 2.0 - Vec2::ONE;
+
+    let ndc_to_world = transform.compute_matrix() * camera.projection_matrix.inverse();
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(0.1));
+    Some((near, (far - near).normalize()))
+}
+
+// Distance along the ray to a sphere around the body's colliders, close enough for picking
+fn ray_hit(origin: Vec3, dir: Vec3, centre: Vec3, radius: f32) -> Option<f32> {
+    let m = origin - centre;
+    let b = m.dot(dir);
+    let c = m.length_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = -b - discriminant.sqrt();
+    (t > 0.0).then(|| t)
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn drag_system(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    mouse_input: Res<Input<MouseButton>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraController>>,
+    bodies: Query<(
+        Entity,
+        &Body,
+        &GlobalTransform,
+        Option<&ColliderSphere>,
+        Option<&ColliderBox>,
+    )>,
+    mut drags: Query<(Entity, &Drag, Option<&mut TargetFollow>)>,
+) {
+    let (camera, camera_transform) = match cameras.iter().next() {
+        Some(camera) => camera,
+        None => return,
+    };
+    let ray = cursor_ray(&windows, camera, camera_transform);
+
+    if mouse_input.just_released(MouseButton::Left) {
+        for (e, _, _) in drags.iter() {
+            commands.entity(e).despawn();
+        }
+        return;
+    }
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+        let (origin, dir) = match ray {
+            Some(ray) => ray,
+            None => return,
+        };
+
+        let closest = bodies
+            .iter()
+            .filter(|(_, body, ..)| body.body_type == RigidBody::Dynamic)
+            .filter_map(|(e, body, transform, sphere, cube)| {
+                let radius = match (sphere, cube) {
+                    (Some(sphere), _) => sphere.radius,
+                    (None, Some(cube)) => {
+                        cube.points.iter().map(|p| p.length()).fold(0.0, f32::max)
+                    }
+                    (None, None) => return None,
+                };
+                let t = ray_hit(origin, dir, transform.translation, radius)?;
+                Some((e, body, transform, t))
+            })
+            .min_by(|a, b| a.3.partial_cmp(&b.3).unwrap());
+
+        if let Some((e, body, transform, distance)) = closest {
+            let hit = origin + dir * distance;
+            commands
+                .spawn()
+                .insert(TargetFollow::new(
+                    e,
+                    body.world_to_local(transform, hit),
+                    hit,
+                ))
+                .insert(Drag { distance })
+                .insert(Name::new("Drag"));
+        }
+        return;
+    }
+
+    // move the target along with the cursor, which wakes the body. The follow is removed when its
+    // body is despawned, the drag goes with it
+    for (e, drag, follow) in drags.iter_mut() {
+        let mut follow = match follow {
+            Some(follow) => follow,
+            None => {
+                commands.entity(e).despawn();
+                continue;
+            }
+        };
+        if let Some((origin, dir)) = ray {
+            follow.target = origin + dir * drag.distance;
+        }
+    }
+}
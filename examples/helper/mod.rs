@@ -1,4 +1,5 @@
 pub mod camera_controller;
+pub mod drag;
 pub mod editor;
 pub mod fps;
 pub mod reset;
@@ -8,6 +9,7 @@ use bevy_inspector_egui::InspectorPlugin;
 
 use bevy_physics_weekend::{debug::PhysicsReport, PhysicsConfig};
 pub use camera_controller::*;
+pub use drag::*;
 pub use editor::*;
 pub use fps::*;
 pub use reset::*;
@@ -18,6 +20,7 @@ impl Plugin for HelperPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(EditorPlugin)
             .add_plugin(CameraControllerPlugin)
+            .add_plugin(DragPlugin)
             .add_plugin(ResetPlugin)
             .add_plugin(FPSPlugin)
             .add_plugin(InspectorPlugin::<PhysicsReport>::new())
@@ -1,5 +1,4 @@
 use bevy::prelude::*;
-use bevy_physics_weekend::Physics;
 
 pub struct ResetPlugin;
 
@@ -12,10 +11,8 @@ impl Plugin for ResetPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ResetEvent>()
             .add_startup_system(setup)
-            .add_system_to_stage(
-                CoreStage::PostUpdate,
-                reset_level.before(Physics::PreUpdate),
-            );
+            // commands are applied at the end of the stage, before the physics stage runs
+            .add_system_to_stage(CoreStage::PostUpdate, reset_level);
     }
 }
 
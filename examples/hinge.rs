@@ -0,0 +1,220 @@
+mod helper;
+use bevy::prelude::*;
+use bevy_physics_weekend::{
+    colliders::ColliderBox,
+    constraints::{AngularMotor, HingeJoint, JointBroken, MotorTarget},
+    debug::PhysicsDebugPlugin,
+    primitives::{Body, PhysicsInterpolation, RigidBody},
+    PhysicsPlugin,
+};
+use helper::HelperPlugin;
+
+fn main() {
+    App::new()
+        .insert_resource(Msaa { samples: 4 })
+        .insert_resource(WindowDescriptor {
+            title: "Physics Hinge".to_string(),
+            vsync: false, // just for testing
+            ..Default::default()
+        })
+        .add_plugins(DefaultPlugins)
+        .add_plugin(HelperPlugin)
+        // our plugin
+        .add_plugin(PhysicsPlugin)
+        .add_plugin(PhysicsDebugPlugin)
+        .add_startup_system(setup)
+        .add_system(setup_level)
+        .add_system(motor_system)
+        .add_system(broken_system)
+        .run();
+}
+
+#[derive(Component)]
+struct Wheel;
+
+fn setup_level(
+    mut ev_reset: EventReader<helper::ResetEvent>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for _ in ev_reset.iter() {
+        info!("Reset");
+        info!("Press `Space` to reverse the wheel, drag the door hard to break it off");
+
+        let post_material = materials.add(StandardMaterial {
+            base_color: Color::DARK_GRAY,
+            ..Default::default()
+        });
+
+        // Door, swings around the post up to 90 degrees either way
+        let post_half_extents = Vec3::new(0.05, 1.0, 0.05);
+        let post = commands
+            .spawn_bundle(PbrBundle {
+                transform: Transform::from_xyz(-0.05, 1.1, 0.0),
+                mesh: meshes.add(Mesh::from(shape::Box::new(0.1, 2.0, 0.1))),
+                material: post_material.clone(),
+                ..Default::default()
+            })
+            .insert(Body {
+                body_type: RigidBody::Static,
+                ..Default::default()
+            })
+            .insert(ColliderBox::from(post_half_extents * 2.0))
+            .insert(helper::Reset)
+            .insert(Name::new("Post"))
+            .id();
+
+        let door_size = Vec3::new(1.0, 2.0, 0.1);
+        let door = commands
+            .spawn_bundle(PbrBundle {
+                transform: Transform::from_xyz(0.55, 1.1, 0.0),
+                mesh: meshes.add(Mesh::from(shape::Box::new(
+                    door_size.x,
+                    door_size.y,
+                    door_size.z,
+                ))),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.6, 0.4, 0.2),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .insert(Body {
+                inv_mass: 0.5,
+                elasticity: 0.2,
+                friction: 0.5,
+                angular_velocity: Vec3::new(0.0, 2.0, 0.0),
+                ..Default::default()
+            })
+            .insert(ColliderBox::from(door_size))
+            .insert(PhysicsInterpolation::default())
+            .insert(helper::Reset)
+            .insert(Name::new("Door"))
+            .id();
+
+        commands
+            .spawn()
+            .insert(
+                HingeJoint::new(post, door, Vec3::new(0.0, 1.1, 0.0), Vec3::Y)
+                    .with_limits(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2)
+                    .with_break_limits(500.0, 500.0),
+            )
+            .insert(helper::Reset)
+            .insert(Name::new("Door Hinge"));
+
+        // Wheel, driven around its axle by the motor
+        let axle = commands
+            .spawn_bundle(PbrBundle {
+                transform: Transform::from_xyz(3.0, 1.5, -0.3),
+                mesh: meshes.add(Mesh::from(shape::Box::new(0.2, 0.2, 0.2))),
+                material: post_material,
+                ..Default::default()
+            })
+            .insert(Body {
+                body_type: RigidBody::Static,
+                ..Default::default()
+            })
+            .insert(ColliderBox::from(Vec3::splat(0.2)))
+            .insert(helper::Reset)
+            .insert(Name::new("Axle"))
+            .id();
+
+        let wheel_size = Vec3::new(1.6, 1.6, 0.2);
+        let wheel = commands
+            .spawn_bundle(PbrBundle {
+                transform: Transform::from_xyz(3.0, 1.5, 0.0),
+                mesh: meshes.add(Mesh::from(shape::Box::new(
+                    wheel_size.x,
+                    wheel_size.y,
+                    wheel_size.z,
+                ))),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.8, 0.2, 0.2),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .insert(Body {
+                inv_mass: 1.0,
+                elasticity: 0.2,
+                friction: 0.5,
+                ..Default::default()
+            })
+            .insert(ColliderBox::from(wheel_size))
+            .insert(PhysicsInterpolation::default())
+            .insert(helper::Reset)
+            .insert(Name::new("Wheel"))
+            .id();
+
+        commands
+            .spawn()
+            .insert(
+                HingeJoint::new(axle, wheel, Vec3::new(3.0, 1.5, -0.1), Vec3::Z)
+                    .with_motor(AngularMotor::velocity(2.0, 50.0)),
+            )
+            .insert(Wheel)
+            .insert(helper::Reset)
+            .insert(Name::new("Wheel Hinge"));
+    }
+}
+
+fn motor_system(input: Res<Input<KeyCode>>, mut query: Query<&mut HingeJoint, With<Wheel>>) {
+    if input.just_pressed(KeyCode::Space) {
+        for mut hinge in query.iter_mut() {
+            if let Some(motor) = hinge.motor.as_mut() {
+                if let MotorTarget::Velocity(speed) = motor.target {
+                    motor.target = MotorTarget::Velocity(-speed);
+                }
+            }
+        }
+    }
+}
+
+fn broken_system(mut ev_broken: EventReader<JointBroken>) {
+    for broken in ev_broken.iter() {
+        info!(
+            "{:?} broke with an impulse of {}",
+            broken.joint, broken.impulse
+        );
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // camera
+    commands
+        .spawn_bundle(PerspectiveCameraBundle {
+            transform: Transform::from_xyz(1.5, 3.0, 8.0)
+                .looking_at(Vec3::new(1.5, 1.0, 0.0), Vec3::Y),
+            ..Default::default()
+        })
+        .insert(helper::CameraController::default())
+        .insert(Name::new("Camera"));
+
+    // light
+    helper::spawn_light(&mut commands);
+
+    //Ground
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(100.0, 1.0, 100.0))),
+            transform: Transform::from_xyz(0.0, -0.5, 0.0),
+            material: materials.add(StandardMaterial {
+                base_color: Color::GREEN,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .insert(Body {
+            body_type: RigidBody::Static,
+            friction: 0.5,
+            elasticity: 0.5,
+            ..Default::default()
+        })
+        .insert(ColliderBox::from(Vec3::new(100.0, 1.0, 100.0)))
+        .insert(Name::new("Ground"));
+}
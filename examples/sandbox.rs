@@ -3,7 +3,7 @@ use bevy::prelude::*;
 use bevy_physics_weekend::{
     colliders::{ColliderBox, ColliderSphere},
     debug::PhysicsDebugPlugin,
-    primitives::Body,
+    primitives::{Body, PhysicsInterpolation, RigidBody},
     PhysicsPlugin,
 };
 use helper::HelperPlugin;
@@ -58,6 +58,7 @@ fn setup_level(
                 })
                 //.insert(Bounded::<aabb::Aabb>::default())
                 .insert(ColliderSphere::new(1.0))
+                .insert(PhysicsInterpolation::default())
                 .insert(helper::Reset)
                 .insert(Name::new("Sphere"));
         }
@@ -82,7 +83,21 @@ fn setup_level(
             })
             .insert(ColliderBox::from(Vec3::ONE))
             .insert(helper::Reset)
-            .insert(Name::new("Box"));
+            .insert(Name::new("Box"))
+            .with_children(|parent| {
+                // not a body, just follows the box around
+                parent
+                    .spawn_bundle(PbrBundle {
+                        transform: Transform::from_xyz(0.0, 0.0, 0.6),
+                        mesh: meshes.add(shape::Box::new(0.2, 0.2, 0.2).into()),
+                        material: materials.add(StandardMaterial {
+                            base_color: Color::RED,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    })
+                    .insert(Name::new("Marker"));
+            });
 
         commands
             .spawn_bundle(PbrBundle {
@@ -141,7 +156,7 @@ fn setup(
             ..Default::default()
         })
         .insert(Body {
-            inv_mass: 0.0,
+            body_type: RigidBody::Static,
             friction: 0.5,
             elasticity: 0.5,
             ..Default::default()
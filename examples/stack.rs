@@ -77,7 +77,7 @@ fn enter_play_system(
                     ..Default::default()
                 })
                 .insert(Body {
-                    inv_mass: 0.0,
+                    body_type: RigidBody::Static,
                     friction: 0.2,
                     elasticity: 0.9,
                     ..Default::default()
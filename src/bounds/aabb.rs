@@ -8,7 +8,7 @@ use core::panic;
 /// Defines an axis-aligned bounding box in mesh space - that is - the bounding box is located at
 /// the mesh's origin, but the current [GlobalTransform] has been used to rotate and scale the mesh
 /// to compute a valid AABB. This reduces float error when the mesh is located far from the origin.
-#[derive(Debug, Clone, Default, Component, Reflect)]
+#[derive(Debug, Clone, Default, PartialEq, Component, Reflect)]
 pub struct Aabb {
     /// The coordinates of the point located at the minimum x, y, and z coordinate. This can also
     /// be thought of as the length of the -x, -y, -z axes that extend from the origin and touch
@@ -85,14 +85,16 @@ impl Aabb {
         Aabb { minimums, maximums }
     }
 
-    pub fn expand_velocity(&mut self, velocity: Vec3) {
-        let mins = self.minimums - velocity;
-        self.minimums = Vec3::select(mins.cmplt(self.minimums), mins, self.minimums);
-        self.maximums = Vec3::select(mins.cmpgt(self.maximums), mins, self.maximums);
+    /// Sweeps the bounding box along the displacement, covering both the start and end of the move
+    pub fn expand_velocity(&mut self, displacement: Vec3) {
+        self.minimums = self.minimums.min(self.minimums + displacement);
+        self.maximums = self.maximums.max(self.maximums + displacement);
+    }
 
-        let maxs = self.maximums + velocity;
-        self.minimums = Vec3::select(maxs.cmplt(self.minimums), maxs, self.minimums);
-        self.maximums = Vec3::select(maxs.cmpgt(self.maximums), maxs, self.maximums);
+    /// Grows the bounding box by the amount in every direction
+    pub fn expand(&mut self, amount: f32) {
+        self.minimums -= Vec3::splat(amount);
+        self.maximums += Vec3::splat(amount);
     }
 
 }
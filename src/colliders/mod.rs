@@ -1,14 +1,16 @@
 use bevy::{
-    math::{Mat3, Vec3},
+    math::{Mat3, Quat, Vec3},
     prelude::{ Component, GlobalTransform, Mesh}, render::{mesh::Indices, render_resource::PrimitiveTopology},
 };
 
-use crate::bounds::Bounds;
+use crate::bounds::{aabb::Aabb, Bounds};
 
 pub trait Collider {
     fn support(&self, dir: Vec3, transform: &GlobalTransform, bias: f32) -> Vec3;
     fn fastest_linear_speed(&self, angular_velocity: Vec3, center_of_mass: Vec3, dir: Vec3) -> f32;
     fn shape_type(&self) -> ColliderType;
+    /// Bounds of the shape with the given rotation, relative to the body translation
+    fn aabb(&self, rotation: Quat) -> Aabb;
 }
 
 #[derive(Component)]
@@ -49,6 +51,10 @@ impl Collider for ColliderSphere {
     fn shape_type(&self) -> ColliderType {
         ColliderType::Sphere
     }
+
+    fn aabb(&self, _rotation: Quat) -> Aabb {
+        Aabb::from_extents(Vec3::splat(-self.radius), Vec3::splat(self.radius))
+    }
 }
 
 #[derive(Component)]
@@ -153,6 +159,14 @@ impl Collider for ColliderBox {
     fn shape_type(&self) -> ColliderType {
         ColliderType::Box
     }
+
+    fn aabb(&self, rotation: Quat) -> Aabb {
+        let (minimums, maximums) = self.points.iter().map(|pt| rotation * *pt).fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(minimums, maximums), pt| (pt.min(minimums), pt.max(maximums)),
+        );
+        Aabb::from_extents(minimums, maximums)
+    }
 }
 
 
@@ -1,390 +1,316 @@
-use super::{quat_left, quat_right, Constraint, ConstraintConfig};
-use crate::{
-    math::{lcp_gauss_seidel, MatMN, MatN, VecN},
-    prelude::Body,
-};
 use bevy::prelude::*;
 
-pub struct ConstraintConstantVelocity {
+use super::{
+    applied_impulse, exceeded_limit, quat_jacobian, quat_left, quat_right, set_jacobian_row,
+    soft_erp, soften_rows, BreakLimits, Constraint, ConstraintBodies, ConstraintConfig,
+    SoftConstraint,
+};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
+
+const ROWS: usize = 3;
+const TWIST_ROW: usize = 1;
+const SWING_ROW: usize = 2;
+
+/// Ball and socket joint that limits how far body_b can swing away from the twist axis and how far
+/// it can twist around it, for shoulders and hips. The anchor and axes are given in world space,
+/// the joint takes the bodies' poses on the first step it runs as its rest pose.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ConeTwistJoint {
     config: ConstraintConfig,
+    world_anchor: Vec3,
+    world_axis: Vec3,
+    world_swing_axis: Vec3,
+    initialized: bool,
     // the initial relative quaternion q1^-1 * q2
     q0: Quat,
-    jacobian: MatMN<2, 12>,
-    cached_lambda: VecN<2>,
-    baumgarte: f32,
+    // the reference swing axis in body_a's space
+    swing_axis_a: Vec3,
+
+    /// Largest swing around the reference swing axis and the axis perpendicular to it, in
+    /// radians. Together they make an elliptical cone body_b can swing inside.
+    pub swing_limits: Vec2,
+    /// Lower and upper twist around the twist axis, in radians
+    pub twist_limits: (f32, f32),
+    pub break_limits: Option<BreakLimits>,
+    /// Makes the joint springy, None holds it rigidly
+    pub soft: Option<SoftConstraint>,
+
+    jacobian: MatMN<ROWS, 12>,
+    cached_lambda: VecN<ROWS>,
+    lambda_min: VecN<ROWS>,
+    lambda_max: VecN<ROWS>,
+    baumgarte: VecN<ROWS>,
+    // the soft constraint's softness, as a multiple of each row's effective inverse mass
+    joint_softness: f32,
+    swing: Vec2,
+    twist: f32,
 }
 
-impl ConstraintConstantVelocity {
-    pub fn new(config: ConstraintConfig, q0: Quat) -> Self {
+impl ConeTwistJoint {
+    /// The swing axis is the first of the two the swing limits are measured around, it's made
+    /// perpendicular to the twist axis
+    pub fn new(
+        body_a: Entity,
+        body_b: Entity,
+        anchor: Vec3,
+        twist_axis: Vec3,
+        swing_axis: Vec3,
+    ) -> Self {
+        let twist_axis = twist_axis.normalize();
+        let mut swing_axis =
+            (swing_axis - twist_axis * swing_axis.dot(twist_axis)).normalize_or_zero();
+        if swing_axis == Vec3::ZERO {
+            swing_axis = twist_axis.any_orthonormal_vector();
+        }
         Self {
-            config,
-            q0,
+            config: ConstraintConfig {
+                handle_a: body_a,
+                handle_b: body_b,
+                ..ConstraintConfig::default()
+            },
+            world_anchor: anchor,
+            world_axis: twist_axis,
+            world_swing_axis: swing_axis,
+            initialized: false,
+            q0: Quat::IDENTITY,
+            swing_axis_a: Vec3::ZERO,
+            swing_limits: Vec2::splat(std::f32::consts::FRAC_PI_4),
+            twist_limits: (-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4),
+            break_limits: None,
+            soft: None,
+            joint_softness: 0.0,
             jacobian: MatMN::zero(),
             cached_lambda: VecN::zero(),
-            baumgarte: 0.0,
+            lambda_min: VecN::zero(),
+            lambda_max: VecN::zero(),
+            baumgarte: VecN::zero(),
+            swing: Vec2::ZERO,
+            twist: 0.0,
         }
     }
-}
-
-impl Constraint for ConstraintConstantVelocity {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            // get the orientation information of the bodies
-            let q1 = trans_a.rotation;
-            let q2 = trans_b.rotation;
-            let q0_inv = self.q0.inverse();
-            let q1_inv = q1.inverse();
-
-            // the axis is defined in the local space of body_a
-            let cv = self.config.axis_a;
-
-            let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
-            let p_t = p.transpose(); // pointless but self documenting
-
-            let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
-            let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
-
-            self.jacobian = MatMN::zero();
-
-            // distance constraint
-            {
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
-
-            // the quaternion jacobian
-            {
-                const IDX: usize = 1;
-
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[1][0] = j1.x;
-                self.jacobian.rows[1][1] = j1.y;
-                self.jacobian.rows[1][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, cv)) * -0.5;
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][3] = j2.x;
-                self.jacobian.rows[1][4] = j2.y;
-                self.jacobian.rows[1][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[1][6] = j3.x;
-                self.jacobian.rows[1][7] = j3.y;
-                self.jacobian.rows[1][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, cv)) * 0.5;
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][9] = j4.x;
-                self.jacobian.rows[1][10] = j4.y;
-                self.jacobian.rows[1][11] = j4.z;
-            }
 
-            // apply warm starting from last frame
-            let impulses = self.jacobian.transpose() * self.cached_lambda;
-            self.config.apply_impulses(bodies, impulses);
-
-            // calculate the baumgarte stabilization
-            let c = r.dot(r);
-            let c = f32::max(0.0, c - 0.01);
-            const BETA: f32 = 0.05;
-            self.baumgarte = (BETA / dt_sec) * c;
-        }
+    pub fn with_swing_limits(mut self, swing_u: f32, swing_v: f32) -> Self {
+        self.swing_limits = Vec2::new(swing_u, swing_v);
+        self
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
-        let jacobian_transpose = self.jacobian.transpose();
-
-        // build the system of equations
-        let q_dt = self.config.get_velocities(bodies);
-        let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
-        let mut rhs = self.jacobian * q_dt * -1.0;
-        rhs[0] -= self.baumgarte;
-
-        // solve for the Lagrange multipliers
-        let lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
-
-        // apply the impulses
-        let impulses = jacobian_transpose * lambda_n;
-        self.config.apply_impulses(bodies, impulses);
+    pub fn with_twist_limits(mut self, lower: f32, upper: f32) -> Self {
+        self.twist_limits = (lower, upper);
+        self
+    }
 
-        // accumulate the impulses for warm starting
-        self.cached_lambda += lambda_n;
+    /// Lets the joint give like a damped spring instead of holding rigidly
+    pub fn with_frequency(mut self, frequency: f32, damping_ratio: f32) -> Self {
+        self.soft = Some(SoftConstraint::new(frequency, damping_ratio));
+        self
     }
 
-    fn post_solve(&mut self) {
-        // limit the warm starting to reasonable limits
-        for cached_lambda in self.cached_lambda.iter_mut() {
-            if !cached_lambda.is_finite() {
-                *cached_lambda = 0.0
-            }
+    /// Breaks the joint once it has to hold more than the force or torque
+    pub fn with_break_limits(mut self, force: f32, torque: f32) -> Self {
+        self.break_limits = Some(BreakLimits { force, torque });
+        self
+    }
 
-            const LIMIT: f32 = 20.0;
-            if *cached_lambda > LIMIT {
-                *cached_lambda = LIMIT;
-            }
-            if *cached_lambda < -LIMIT {
-                *cached_lambda = -LIMIT;
-            }
-        }
+    /// The two axes swing is measured around, perpendicular to the twist axis, in world space at
+    /// the rest pose
+    pub fn swing_axes(&self) -> (Vec3, Vec3) {
+        (
+            self.world_swing_axis,
+            self.world_axis.cross(self.world_swing_axis),
+        )
     }
-}
 
-pub struct ConstraintConstantVelocityLimited {
-    config: ConstraintConfig,
-    // the initial relative quaternion q1^-1 * q2
-    q0: Quat,
-    jacobian: MatMN<4, 12>,
-    cached_lambda: VecN<4>,
-    baumgarte: f32,
-    angle_u: f32,
-    angle_v: f32,
-    is_angle_violated_u: bool,
-    is_angle_violated_v: bool,
-}
+    /// Current swing of body_b around each of the swing axes, in radians
+    pub fn swing(&self) -> Vec2 {
+        self.swing
+    }
 
-impl ConstraintConstantVelocityLimited {
-    pub fn new(config: ConstraintConfig, q0: Quat) -> Self {
-        Self {
-            config,
-            q0,
-            jacobian: MatMN::zero(),
-            cached_lambda: VecN::zero(),
-            baumgarte: 0.0,
-            angle_u: 0.0,
-            angle_v: 0.0,
-            is_angle_violated_u: false,
-            is_angle_violated_v: false,
-        }
+    /// Current twist of body_b around the twist axis, in radians
+    pub fn twist(&self) -> f32 {
+        self.twist
     }
 }
 
-impl Constraint for ConstraintConstantVelocityLimited {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            // get the orientation information of the bodies
-            let q1 = trans_a.rotation;
-            let q2 = trans_b.rotation;
-            let q0_inv = self.q0.inverse();
-            let q1_inv = q1.inverse();
-
-            // the axis is defined in the local space of body_a
-            let cv = self.config.axis_a;
-            let (u, v) = cv.any_orthonormal_pair();
-
-            let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
-            let p_t = p.transpose(); // pointless but self documenting
-
-            let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
-            let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
-
-            // check the constraint's angular limits
-            let qr = q1_inv * q2;
-            let qrr = qr * q0_inv;
-            self.angle_u = 2.0 * qrr.xyz().dot(u).asin().to_degrees();
-            self.angle_v = 2.0 * qrr.xyz().dot(v).asin().to_degrees();
-
-            self.is_angle_violated_u = self.angle_u.abs() > 45.0;
-            self.is_angle_violated_v = self.angle_v.abs() > 45.0;
-
-            self.jacobian = MatMN::zero();
-
-            // first row is primary distance constraint that holds the anchor points together
-            {
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
-
-            // the quaternion jacobian
-            {
-                const IDX: usize = 1;
-
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[1][0] = j1.x;
-                self.jacobian.rows[1][1] = j1.y;
-                self.jacobian.rows[1][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, cv)) * -0.5;
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][3] = j2.x;
-                self.jacobian.rows[1][4] = j2.y;
-                self.jacobian.rows[1][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[1][6] = j3.x;
-                self.jacobian.rows[1][7] = j3.y;
-                self.jacobian.rows[1][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, cv)) * 0.5;
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][9] = j4.x;
-                self.jacobian.rows[1][10] = j4.y;
-                self.jacobian.rows[1][11] = j4.z;
-            }
+impl Constraint for ConeTwistJoint {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
 
-            if self.is_angle_violated_u {
-                const IDX: usize = 1;
-
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[2][0] = j1.x;
-                self.jacobian.rows[2][1] = j1.y;
-                self.jacobian.rows[2][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, u)) * -0.5;
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][3] = j2.x;
-                self.jacobian.rows[2][4] = j2.y;
-                self.jacobian.rows[2][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[2][6] = j3.x;
-                self.jacobian.rows[2][7] = j3.y;
-                self.jacobian.rows[2][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, u)) * 0.5;
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][9] = j4.x;
-                self.jacobian.rows[2][10] = j4.y;
-                self.jacobian.rows[2][11] = j4.z;
-            }
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        if !self.initialized {
+            let (config, q0) = ConstraintConfig::from_world(
+                bodies,
+                self.config.handle_a,
+                self.config.handle_b,
+                self.world_anchor,
+                self.world_axis,
+            );
+            let (_, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+            self.swing_axis_a = trans_a.rotation.inverse() * self.world_swing_axis;
+            self.config = config;
+            self.q0 = q0;
+            self.initialized = true;
+        }
 
-            if self.is_angle_violated_v {
-                const IDX: usize = 1;
-
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[3][0] = j1.x;
-                self.jacobian.rows[3][1] = j1.y;
-                self.jacobian.rows[3][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, v)) * -0.5;
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][3] = j2.x;
-                self.jacobian.rows[3][4] = j2.y;
-                self.jacobian.rows[3][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[3][6] = j3.x;
-                self.jacobian.rows[3][7] = j3.y;
-                self.jacobian.rows[3][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, v)) * 0.5;
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][9] = j4.x;
-                self.jacobian.rows[3][10] = j4.y;
-                self.jacobian.rows[3][11] = j4.z;
+        let (body_a, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+
+        // get the world space position of the joint from body_a's orientation
+        let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
+
+        // get the world space position of the joint from body_b's orientation
+        let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
+
+        let r = world_anchor_b - world_anchor_a;
+        let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
+        let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
+        let a = world_anchor_a;
+        let b = world_anchor_b;
+
+        // get the orientation information of the bodies, q0 and -q0 are the same rotation so
+        // pick the one that keeps the relative quaternion's w positive
+        let q1 = trans_a.rotation;
+        let q2 = trans_b.rotation;
+        let q1_inv = q1.inverse();
+        let mut q0_inv = self.q0.inverse();
+        if (q1_inv * q2 * q0_inv).w < 0.0 {
+            q0_inv = -q0_inv;
+        }
+        let qrr = q1_inv * q2 * q0_inv;
+
+        // the axes are defined in the local space of body_a
+        let cv = self.config.axis_a;
+        let u = self.swing_axis_a;
+        let v = cv.cross(u);
+
+        // split the relative rotation into a twist around the axis followed by a swing of the
+        // axis, qrr = swing * twist
+        let (swing, twist) = swing_twist(qrr, cv);
+        self.twist = 2.0 * twist.xyz().dot(cv).atan2(twist.w);
+        let swing_angle = 2.0 * swing.xyz().length().atan2(swing.w);
+        let swing_axis = swing.xyz().normalize_or_zero();
+        self.swing = Vec2::new(swing_axis.dot(u), swing_axis.dot(v)) * swing_angle;
+
+        let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
+        let p_t = p.transpose(); // pointless but self documenting
+
+        let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
+        let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
+
+        self.jacobian = MatMN::zero();
+        self.lambda_min = VecN::zero();
+        self.lambda_max = VecN::zero();
+        self.baumgarte = VecN::zero();
+
+        // first row is primary distance constraint that holds the anchor points together
+        set_jacobian_row(
+            &mut self.jacobian,
+            0,
+            (a - b) * 2.0,
+            ra.cross((a - b) * 2.0),
+            (b - a) * 2.0,
+            rb.cross((b - a) * 2.0),
+        );
+        self.lambda_min[0] = f32::NEG_INFINITY;
+        self.lambda_max[0] = f32::INFINITY;
+
+        // calculate the baumgarte stabilization
+        const BETA: f32 = 0.05;
+        let (beta, softness) = soft_erp(self.soft, BETA, dt_sec);
+        self.joint_softness = softness;
+        let c = f32::max(0.0, r.dot(r) - 0.01);
+        self.baumgarte[0] = (beta / dt_sec) * c;
+
+        // the angle rows only exist while their limit is exceeded, they are measured in the
+        // quaternion's vector part like the quaternion jacobians. Along an axis that's
+        // sin(angle / 2) scaled by the cos of half the other rotation, so the limits are too
+        //
+        // the swing is limited by an ellipse, it's pushed back along the direction it swung in as
+        // far as the ellipse reaches in that direction
+        let max_swing = if swing_angle > f32::EPSILON {
+            let (su, sv) = (swing_axis.dot(u), swing_axis.dot(v));
+            let (lu, lv) = (self.swing_limits.x, self.swing_limits.y);
+            1.0 / ((su / lu).powi(2) + (sv / lv).powi(2)).sqrt()
+        } else {
+            f32::INFINITY
+        };
+        let limits = [
+            (
+                TWIST_ROW,
+                cv,
+                self.twist,
+                self.twist_limits,
+                (swing_angle * 0.5).cos(),
+            ),
+            (
+                SWING_ROW,
+                swing_axis,
+                swing_angle,
+                (f32::NEG_INFINITY, max_swing),
+                (self.twist * 0.5).cos(),
+            ),
+        ];
+        for (row, axis, angle, (lower, upper), scale) in limits {
+            match exceeded_limit(angle, lower, upper) {
+                Some((limit, lambda_min, lambda_max)) => {
+                    set_jacobian_row(
+                        &mut self.jacobian,
+                        row,
+                        Vec3::ZERO,
+                        quat_jacobian(mat_a, axis),
+                        Vec3::ZERO,
+                        quat_jacobian(mat_b, axis),
+                    );
+                    self.lambda_min[row] = lambda_min;
+                    self.lambda_max[row] = lambda_max;
+                    let c = qrr.xyz().dot(axis) - scale * (limit * 0.5).sin();
+                    self.baumgarte[row] = (beta / dt_sec) * c;
+                }
+                None => self.cached_lambda[row] = 0.0,
             }
-
-            // apply warm starting from last frame
-            let impulses = self.jacobian.transpose() * self.cached_lambda;
-            self.config.apply_impulses(bodies, impulses);
-
-            // calculate the baumgarte stabilization
-            let c = r.dot(r);
-            let c = f32::max(0.0, c - 0.01);
-            const BETA: f32 = 0.05;
-            self.baumgarte = (BETA / dt_sec) * c;
         }
+
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
+    fn solve(&mut self, bodies: &ConstraintBodies) {
         let jacobian_transpose = self.jacobian.transpose();
 
         // build the system of equations
         let q_dt = self.config.get_velocities(bodies);
         let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
         let mut rhs = self.jacobian * q_dt * -1.0;
-        rhs[0] -= self.baumgarte;
+        for row in 0..ROWS {
+            rhs[row] -= self.baumgarte[row];
+        }
+
+        // a soft joint gives on every row
+        soften_rows(
+            &mut j_w_jt,
+            &mut rhs,
+            &self.cached_lambda,
+            0..ROWS,
+            self.joint_softness,
+        );
 
         // solve for the Lagrange multipliers
-        let mut lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
-
-        // clamp the torque from the angle constraint
-        // we need to make sure it's a restorative torque
-        if self.is_angle_violated_u {
-            if self.angle_u > 0.0 {
-                lambda_n[2] = f32::min(0.0, lambda_n[2]);
-            } else if self.angle_u < 0.0 {
-                lambda_n[2] = f32::max(0.0, lambda_n[2]);
-            }
-        }
-        if self.is_angle_violated_v {
-            if self.angle_v > 0.0 {
-                lambda_n[3] = f32::min(0.0, lambda_n[3]);
-            } else if self.angle_v < 0.0 {
-                lambda_n[3] = f32::max(0.0, lambda_n[3]);
-            }
+        let mut lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
+
+        // accumulate the impulses, clamped so the limits only ever restore
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        for row in 0..ROWS {
+            self.cached_lambda[row] =
+                self.cached_lambda[row].clamp(self.lambda_min[row], self.lambda_max[row]);
         }
+        lambda_n = self.cached_lambda - old_lambda;
 
         // apply the impulses
         let impulses = jacobian_transpose * lambda_n;
         self.config.apply_impulses(bodies, impulses);
-
-        // accumulate the impulses for warm starting
-        self.cached_lambda += lambda_n;
     }
 
     fn post_solve(&mut self) {
@@ -394,13 +320,28 @@ impl Constraint for ConstraintConstantVelocityLimited {
                 *cached_lambda = 0.0
             }
 
-            const LIMIT: f32 = 20.0;
-            if *cached_lambda > LIMIT {
-                *cached_lambda = LIMIT;
-            }
-            if *cached_lambda < -LIMIT {
-                *cached_lambda = -LIMIT;
-            }
+            const LIMIT: f32 = 1e5;
+            *cached_lambda = cached_lambda.clamp(-LIMIT, LIMIT);
         }
     }
+
+    fn break_limits(&self) -> Option<BreakLimits> {
+        self.break_limits
+    }
+
+    fn applied_impulse(&self) -> (f32, f32) {
+        applied_impulse(&self.jacobian, &self.cached_lambda)
+    }
+}
+
+// Splits the rotation into a twist around the axis followed by a swing of the axis, so
+// q = swing * twist. Half a turn of swing leaves no twist to measure, that's taken as none.
+fn swing_twist(q: Quat, axis: Vec3) -> (Quat, Quat) {
+    let projected = Vec4::from((axis * q.xyz().dot(axis), q.w));
+    let twist = if projected.length_squared() > f32::EPSILON {
+        Quat::from_vec4(projected.normalize())
+    } else {
+        Quat::IDENTITY
+    };
+    (q * twist.inverse(), twist)
 }
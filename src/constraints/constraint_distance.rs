@@ -1,106 +1,230 @@
 use bevy::prelude::*;
 
-use super::{Constraint, ConstraintConfig};
-use crate::{
-    math::{lcp_gauss_seidel, MatMN, MatN, VecN},
-    prelude::Body,
+use super::{
+    applied_impulse, soft_erp, soften_rows, BreakLimits, Constraint, ConstraintBodies,
+    ConstraintConfig, SoftConstraint,
 };
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
 
-pub struct ConstraintDistance {
+/// Keeps the distance between an anchor on each body within `min_length..=max_length`, equal
+/// lengths make a rigid rod and a wider range a rope. Anchors are in each body's space, relative to
+/// its centre of mass. The component can go on either body or on an entity of its own.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct DistanceJoint {
     config: ConstraintConfig,
+
+    pub min_length: f32,
+    pub max_length: f32,
+    /// Spring pulling the anchors towards `rest_length` while inside the limits, None leaves them
+    /// free between the limits
+    pub spring: Option<SoftConstraint>,
+    pub rest_length: f32,
+    pub break_limits: Option<BreakLimits>,
+    /// Makes the joint springy at its limits, None holds them rigidly
+    pub soft: Option<SoftConstraint>,
+
     jacobian: MatMN<1, 12>,
     cached_lambda: VecN<1>,
+    lambda_min: f32,
+    lambda_max: f32,
+    active: bool,
     baumgarte: f32,
+    // the soft constraint's softness, as a multiple of each row's effective inverse mass
+    joint_softness: f32,
 }
 
-impl ConstraintDistance {
-    pub fn new(config: ConstraintConfig) -> Self {
-        ConstraintDistance {
-            config,
+impl DistanceJoint {
+    /// A rigid rod of the given length between the anchors
+    pub fn new(
+        body_a: Entity,
+        anchor_a: Vec3,
+        body_b: Entity,
+        anchor_b: Vec3,
+        length: f32,
+    ) -> Self {
+        Self {
+            config: ConstraintConfig {
+                handle_a: body_a,
+                handle_b: body_b,
+                anchor_a,
+                anchor_b,
+                ..ConstraintConfig::default()
+            },
+            min_length: length,
+            max_length: length,
+            spring: None,
+            rest_length: length,
+            break_limits: None,
+            soft: None,
+            joint_softness: 0.0,
             jacobian: MatMN::zero(),
             cached_lambda: VecN::zero(),
+            lambda_min: 0.0,
+            lambda_max: 0.0,
+            active: false,
             baumgarte: 0.0,
         }
     }
+
+    pub fn with_limits(mut self, min_length: f32, max_length: f32) -> Self {
+        self.min_length = min_length;
+        self.max_length = max_length;
+        self
+    }
+
+    /// Pulls the anchors towards the rest length like a damped spring while they're inside the
+    /// limits
+    pub fn with_spring(mut self, rest_length: f32, frequency: f32, damping_ratio: f32) -> Self {
+        self.rest_length = rest_length;
+        self.spring = Some(SoftConstraint::new(frequency, damping_ratio));
+        self
+    }
+
+    /// Lets the joint give like a damped spring instead of holding rigidly
+    pub fn with_frequency(mut self, frequency: f32, damping_ratio: f32) -> Self {
+        self.soft = Some(SoftConstraint::new(frequency, damping_ratio));
+        self
+    }
+
+    /// Breaks the joint once it has to hold more than the force or torque
+    pub fn with_break_limits(mut self, force: f32, torque: f32) -> Self {
+        self.break_limits = Some(BreakLimits { force, torque });
+        self
+    }
+
+    pub fn anchors(&self) -> (Vec3, Vec3) {
+        (self.config.anchor_a, self.config.anchor_b)
+    }
 }
 
-impl Constraint for ConstraintDistance {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            {
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-            }
-
-            {
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-            }
-
-            {
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-            }
-
-            {
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
-
-            // apply warm starting from the last frame
-            let impulses = self.jacobian.transpose() * self.cached_lambda;
-            self.config.apply_impulses(bodies, impulses);
-
-            // calculate the baumgarte stabilization
-            let mut c = r.dot(r);
-            c = f32::max(0.0, c - 0.01);
-            let beta = 0.05;
-            self.baumgarte = (beta / dt_sec) * c;
+impl Constraint for DistanceJoint {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
+
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        let (body_a, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+
+        let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
+        let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
+
+        let r = world_anchor_b - world_anchor_a;
+        let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
+        let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
+        let length = r.length();
+        let normal = if length > f32::EPSILON {
+            r / length
+        } else {
+            Vec3::Y
+        };
+
+        // a single row measuring how fast the anchors are moving apart
+        {
+            let j1 = -normal;
+            self.jacobian.rows[0][0] = j1.x;
+            self.jacobian.rows[0][1] = j1.y;
+            self.jacobian.rows[0][2] = j1.z;
+        }
+
+        {
+            let j2 = ra.cross(-normal);
+            self.jacobian.rows[0][3] = j2.x;
+            self.jacobian.rows[0][4] = j2.y;
+            self.jacobian.rows[0][5] = j2.z;
+        }
+
+        {
+            let j3 = normal;
+            self.jacobian.rows[0][6] = j3.x;
+            self.jacobian.rows[0][7] = j3.y;
+            self.jacobian.rows[0][8] = j3.z;
         }
+
+        {
+            let j4 = rb.cross(normal);
+            self.jacobian.rows[0][9] = j4.x;
+            self.jacobian.rows[0][10] = j4.y;
+            self.jacobian.rows[0][11] = j4.z;
+        }
+
+        // a negative impulse pulls the anchors together, so the max limit can only pull and the
+        // min limit can only push, equal limits do both. Between the limits the spring uses the
+        // same row, pulling either way towards the rest length
+        let mut soft = self.soft;
+        let c = if self.min_length >= self.max_length {
+            self.lambda_min = f32::NEG_INFINITY;
+            self.lambda_max = f32::INFINITY;
+            length - self.max_length
+        } else if length > self.max_length {
+            self.lambda_min = f32::NEG_INFINITY;
+            self.lambda_max = 0.0;
+            length - self.max_length
+        } else if length < self.min_length {
+            self.lambda_min = 0.0;
+            self.lambda_max = f32::INFINITY;
+            length - self.min_length
+        } else if self.spring.is_some() {
+            self.lambda_min = f32::NEG_INFINITY;
+            self.lambda_max = f32::INFINITY;
+            soft = self.spring;
+            length - self.rest_length
+        } else {
+            self.active = false;
+            self.cached_lambda = VecN::zero();
+            return;
+        };
+        self.active = true;
+
+        // the last frame's impulse may be in a direction the new limit doesn't allow
+        self.cached_lambda[0] = self.cached_lambda[0].clamp(self.lambda_min, self.lambda_max);
+
+        // apply warm starting from the last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
+
+        // calculate the baumgarte stabilization, the spring is an implicit soft row so it stays
+        // stable however stiff it is
+        let (beta, softness) = soft_erp(soft, 0.1, dt_sec);
+        self.joint_softness = softness;
+        self.baumgarte = (beta / dt_sec) * c;
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
+    fn solve(&mut self, bodies: &ConstraintBodies) {
+        if !self.active {
+            return;
+        }
+
         let jacobian_transpose = self.jacobian.transpose();
 
         // build the system of equations
         let q_dt = self.config.get_velocities(bodies);
         let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
         let mut rhs = self.jacobian * q_dt * -1.0;
         rhs[0] -= self.baumgarte;
 
+        // a soft joint gives like a spring at its limits, and the spring gives between them
+        soften_rows(
+            &mut j_w_jt,
+            &mut rhs,
+            &self.cached_lambda,
+            0..1,
+            self.joint_softness,
+        );
+
         // solve for the Lagrange multipliers
-        let lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
+        let mut lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
+
+        // accumulate the impulses and clamp to the direction the active limit allows
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        self.cached_lambda[0] = self.cached_lambda[0].clamp(self.lambda_min, self.lambda_max);
+        lambda_n = self.cached_lambda - old_lambda;
 
         // apply the impulses
         let impulses = jacobian_transpose * lambda_n;
         self.config.apply_impulses(bodies, impulses);
-
-        // accumulate the impulses for warm starting
-        self.cached_lambda += lambda_n;
     }
 
     fn post_solve(&mut self) {
@@ -110,11 +234,14 @@ impl Constraint for ConstraintDistance {
         }
 
         const LIMIT: f32 = 1e5;
-        if self.cached_lambda[0] > LIMIT {
-            self.cached_lambda[0] = LIMIT;
-        }
-        if self.cached_lambda[0] < -LIMIT {
-            self.cached_lambda[0] = -LIMIT;
-        }
+        self.cached_lambda[0] = self.cached_lambda[0].clamp(-LIMIT, LIMIT);
+    }
+
+    fn break_limits(&self) -> Option<BreakLimits> {
+        self.break_limits
+    }
+
+    fn applied_impulse(&self) -> (f32, f32) {
+        applied_impulse(&self.jacobian, &self.cached_lambda)
     }
 }
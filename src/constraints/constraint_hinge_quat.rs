@@ -1,397 +1,298 @@
-use super::{quat_left, quat_right, Constraint, ConstraintConfig};
-use crate::{
-    math::{lcp_gauss_seidel, MatMN, MatN, VecN},
-    prelude::Body,
-};
 use bevy::prelude::*;
 
-pub struct ConstraintHingeQuat {
+use super::{
+    applied_impulse, constraint_motor::AngularMotor, exceeded_limit, quat_jacobian, quat_left,
+    quat_right, set_jacobian_row, soft_erp, soften_rows, BreakLimits, Constraint, ConstraintBodies,
+    ConstraintConfig, SoftConstraint,
+};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
+
+const LIMIT_ROW: usize = 3;
+const MOTOR_ROW: usize = 4;
+
+/// Lets body_b only rotate around an axis through the anchor relative to body_a, like a door or a
+/// wheel. The anchor and axis are given in world space, the joint takes the bodies' poses on the
+/// first step it runs as its rest pose.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct HingeJoint {
     config: ConstraintConfig,
+    world_anchor: Vec3,
+    world_axis: Vec3,
+    initialized: bool,
     // the initial relative quaternion q1^-1 * q2
     q0: Quat,
-    jacobian: MatMN<3, 12>,
-    cached_lambda: VecN<3>,
-    baumgarte: f32,
+
+    /// Lower and upper angle of body_b relative to its rest pose, in radians
+    pub limits: Option<(f32, f32)>,
+    pub motor: Option<AngularMotor>,
+    pub break_limits: Option<BreakLimits>,
+    /// Makes the joint springy, None holds it rigidly
+    pub soft: Option<SoftConstraint>,
+
+    jacobian: MatMN<5, 12>,
+    cached_lambda: VecN<5>,
+    lambda_min: VecN<5>,
+    lambda_max: VecN<5>,
+    baumgarte: VecN<5>,
+    motor_softness: f32,
+    // the soft constraint's softness, as a multiple of each row's effective inverse mass
+    joint_softness: f32,
+    angle: f32,
+    dt_sec: f32,
 }
 
-impl ConstraintHingeQuat {
-    pub fn new(config: ConstraintConfig, q0: Quat) -> Self {
+impl HingeJoint {
+    pub fn new(body_a: Entity, body_b: Entity, anchor: Vec3, axis: Vec3) -> Self {
         Self {
-            config,
-            q0,
+            config: ConstraintConfig {
+                handle_a: body_a,
+                handle_b: body_b,
+                ..ConstraintConfig::default()
+            },
+            world_anchor: anchor,
+            world_axis: axis.normalize(),
+            initialized: false,
+            q0: Quat::IDENTITY,
+            limits: None,
+            motor: None,
+            break_limits: None,
+            soft: None,
+            joint_softness: 0.0,
             jacobian: MatMN::zero(),
             cached_lambda: VecN::zero(),
-            baumgarte: 0.0,
+            lambda_min: VecN::zero(),
+            lambda_max: VecN::zero(),
+            baumgarte: VecN::zero(),
+            motor_softness: 0.0,
+            angle: 0.0,
+            dt_sec: 0.0,
         }
     }
-}
-
-impl Constraint for ConstraintHingeQuat {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            // get the orientation information of the bodies
-            let q1 = trans_a.rotation;
-            let q2 = trans_b.rotation;
-            let q0_inv = self.q0.inverse();
-            let q1_inv = q1.inverse();
-
-            let hinge_axis = self.config.axis_a;
-            let (u, v) = hinge_axis.any_orthonormal_pair();
-
-            let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
-            let p_t = p.transpose(); // pointless but self documenting
-
-            let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
-            let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
-
-            self.jacobian = MatMN::zero();
-
-            // the distance constraint
-            {
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
-
-            const IDX: usize = 1;
-
-            // the quaternion jacobians
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[1][0] = j1.x;
-                self.jacobian.rows[1][1] = j1.y;
-                self.jacobian.rows[1][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, u));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][3] = j2.x;
-                self.jacobian.rows[1][4] = j2.y;
-                self.jacobian.rows[1][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[1][6] = j3.x;
-                self.jacobian.rows[1][7] = j3.y;
-                self.jacobian.rows[1][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, u));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][9] = j4.x;
-                self.jacobian.rows[1][10] = j4.y;
-                self.jacobian.rows[1][11] = j4.z;
-            }
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[2][0] = j1.x;
-                self.jacobian.rows[2][1] = j1.y;
-                self.jacobian.rows[2][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, v));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][3] = j2.x;
-                self.jacobian.rows[2][4] = j2.y;
-                self.jacobian.rows[2][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[2][6] = j3.x;
-                self.jacobian.rows[2][7] = j3.y;
-                self.jacobian.rows[2][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, v));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][9] = j4.x;
-                self.jacobian.rows[2][10] = j4.y;
-                self.jacobian.rows[2][11] = j4.z;
-            }
-
-            // apply warm starting from last frame
-            let impulses = self.jacobian.transpose() * self.cached_lambda;
-            self.config.apply_impulses(bodies, impulses);
 
-            // calculate the baumgarte stabilization
-            let c = r.dot(r);
-            let c = f32::max(0.0, c - 0.01);
-            const BETA: f32 = 0.05;
-            self.baumgarte = (BETA / dt_sec) * c;
-        }
+    pub fn with_limits(mut self, lower: f32, upper: f32) -> Self {
+        self.limits = Some((lower, upper));
+        self
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
-        let jacobian_transpose = self.jacobian.transpose();
-
-        // build the system of equations
-        let q_dt = self.config.get_velocities(bodies);
-        let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
-        let mut rhs = self.jacobian * q_dt * -1.0;
-        rhs[0] -= self.baumgarte;
-
-        // solve for the Lagrange multipliers
-        let lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
-
-        // apply the impulses
-        let impulses = jacobian_transpose * lambda_n;
-        self.config.apply_impulses(bodies, impulses);
-
-        // accumulate the impulses for warm starting
-        self.cached_lambda += lambda_n;
+    pub fn with_motor(mut self, motor: AngularMotor) -> Self {
+        self.motor = Some(motor);
+        self
     }
 
-    fn post_solve(&mut self) {
-        // limit the warm starting to reasonable limits
-        for cached_lambda in self.cached_lambda.iter_mut() {
-            if !cached_lambda.is_finite() {
-                *cached_lambda = 0.0
-            }
+    /// Lets the joint give like a damped spring instead of holding rigidly
+    pub fn with_frequency(mut self, frequency: f32, damping_ratio: f32) -> Self {
+        self.soft = Some(SoftConstraint::new(frequency, damping_ratio));
+        self
+    }
 
-            const LIMIT: f32 = 20.0;
-            if *cached_lambda > LIMIT {
-                *cached_lambda = LIMIT;
-            }
-            if *cached_lambda < -LIMIT {
-                *cached_lambda = -LIMIT;
-            }
-        }
+    /// Breaks the joint once it has to hold more than the force or torque
+    pub fn with_break_limits(mut self, force: f32, torque: f32) -> Self {
+        self.break_limits = Some(BreakLimits { force, torque });
+        self
     }
-}
 
-pub struct ConstraintHingeQuatLimited {
-    config: ConstraintConfig,
-    // the initial relative quaternion q1^-1 * q2
-    q0: Quat,
-    jacobian: MatMN<4, 12>,
-    cached_lambda: VecN<4>,
-    baumgarte: f32,
-    relative_angle: f32, // in degrees
-    is_angle_violated: bool,
+    /// Angle of body_b relative to its rest pose around the axis, in radians
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
 }
 
-impl ConstraintHingeQuatLimited {
-    pub fn new(config: ConstraintConfig, q0: Quat) -> Self {
-        Self {
-            config,
-            q0,
-            jacobian: MatMN::zero(),
-            cached_lambda: VecN::zero(),
-            baumgarte: 0.0,
-            relative_angle: 0.0,
-            is_angle_violated: false,
-        }
+impl Constraint for HingeJoint {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
     }
-}
 
-impl Constraint for ConstraintHingeQuatLimited {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            // get the orientation information of the bodies
-            let q1 = trans_a.rotation;
-            let q2 = trans_b.rotation;
-            let q0_inv = self.q0.inverse();
-            let q1_inv = q1.inverse();
-
-            // the axis is defined in the local space of body_a
-            let hinge_axis = self.config.axis_a;
-            let (u, v) = hinge_axis.any_orthonormal_pair();
-
-            let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
-            let p_t = p.transpose(); // pointless but self documenting
-
-            let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
-            let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
-
-            let qr = q1_inv * q2;
-            let qrr = qr * q0_inv;
-            self.relative_angle = 2.0 * qrr.xyz().dot(hinge_axis).asin().to_degrees();
-
-            // check if there's an angle violation
-            self.is_angle_violated = self.relative_angle.abs() > 45.0;
-
-            self.jacobian = MatMN::zero();
-
-            // first row is the primary distance constraint that holds the anchor points together
-            {
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        if !self.initialized {
+            let (config, q0) = ConstraintConfig::from_world(
+                bodies,
+                self.config.handle_a,
+                self.config.handle_b,
+                self.world_anchor,
+                self.world_axis,
+            );
+            self.config = config;
+            self.q0 = q0;
+            self.initialized = true;
+        }
 
-            const IDX: usize = 1;
-
-            // the quaternion jacobians
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[1][0] = j1.x;
-                self.jacobian.rows[1][1] = j1.y;
-                self.jacobian.rows[1][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, u));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][3] = j2.x;
-                self.jacobian.rows[1][4] = j2.y;
-                self.jacobian.rows[1][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[1][6] = j3.x;
-                self.jacobian.rows[1][7] = j3.y;
-                self.jacobian.rows[1][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, u));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][9] = j4.x;
-                self.jacobian.rows[1][10] = j4.y;
-                self.jacobian.rows[1][11] = j4.z;
-            }
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[2][0] = j1.x;
-                self.jacobian.rows[2][1] = j1.y;
-                self.jacobian.rows[2][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, v));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][3] = j2.x;
-                self.jacobian.rows[2][4] = j2.y;
-                self.jacobian.rows[2][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[2][6] = j3.x;
-                self.jacobian.rows[2][7] = j3.y;
-                self.jacobian.rows[2][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, v));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][9] = j4.x;
-                self.jacobian.rows[2][10] = j4.y;
-                self.jacobian.rows[2][11] = j4.z;
-            }
-            if self.is_angle_violated {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[3][0] = j1.x;
-                self.jacobian.rows[3][1] = j1.y;
-                self.jacobian.rows[3][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, hinge_axis));
-                // TODO: might be a good addition
-                // let tmp = mat_a * Vec4::from((0.0, hinge_axis));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][3] = j2.x;
-                self.jacobian.rows[3][4] = j2.y;
-                self.jacobian.rows[3][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[3][6] = j3.x;
-                self.jacobian.rows[3][7] = j3.y;
-                self.jacobian.rows[3][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, hinge_axis));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][9] = j4.x;
-                self.jacobian.rows[3][10] = j4.y;
-                self.jacobian.rows[3][11] = j4.z;
-            }
+        let (body_a, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+
+        // get the world space position of the hinge from body_a's orientation
+        let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
+
+        // get the world space position of the hinge from body_b's orientation
+        let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
+
+        let r = world_anchor_b - world_anchor_a;
+        let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
+        let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
+        let a = world_anchor_a;
+        let b = world_anchor_b;
+
+        // get the orientation information of the bodies, q0 and -q0 are the same rotation so
+        // pick the one that keeps the relative quaternion's w positive
+        let q1 = trans_a.rotation;
+        let q2 = trans_b.rotation;
+        let q1_inv = q1.inverse();
+        let mut q0_inv = self.q0.inverse();
+        if (q1_inv * q2 * q0_inv).w < 0.0 {
+            q0_inv = -q0_inv;
+        }
+        let qrr = q1_inv * q2 * q0_inv;
+
+        // the axis is defined in the local space of body_a
+        let hinge_axis = self.config.axis_a;
+        let (u, v) = hinge_axis.any_orthonormal_pair();
+        self.angle = 2.0 * qrr.xyz().dot(hinge_axis).atan2(qrr.w);
+
+        let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
+        let p_t = p.transpose(); // pointless but self documenting
+
+        let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
+        let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
+
+        self.jacobian = MatMN::zero();
+        self.lambda_min = VecN::zero();
+        self.lambda_max = VecN::zero();
+        self.baumgarte = VecN::zero();
+
+        // first row is the primary distance constraint that holds the anchor points together
+        set_jacobian_row(
+            &mut self.jacobian,
+            0,
+            (a - b) * 2.0,
+            ra.cross((a - b) * 2.0),
+            (b - a) * 2.0,
+            rb.cross((b - a) * 2.0),
+        );
+
+        // the quaternion jacobians stop any rotation off the hinge axis
+        set_jacobian_row(
+            &mut self.jacobian,
+            1,
+            Vec3::ZERO,
+            quat_jacobian(mat_a, u),
+            Vec3::ZERO,
+            quat_jacobian(mat_b, u),
+        );
+        set_jacobian_row(
+            &mut self.jacobian,
+            2,
+            Vec3::ZERO,
+            quat_jacobian(mat_a, v),
+            Vec3::ZERO,
+            quat_jacobian(mat_b, v),
+        );
+        for row in 0..LIMIT_ROW {
+            self.lambda_min[row] = f32::NEG_INFINITY;
+            self.lambda_max[row] = f32::INFINITY;
+        }
 
-            // apply warm starting from last frame
-            let impulses = self.jacobian.transpose() * self.cached_lambda;
-            self.config.apply_impulses(bodies, impulses);
+        // calculate the baumgarte stabilization
+        const BETA: f32 = 0.05;
+        let (beta, softness) = soft_erp(self.soft, BETA, dt_sec);
+        self.joint_softness = softness;
+        let c = f32::max(0.0, r.dot(r) - 0.01);
+        self.baumgarte[0] = (beta / dt_sec) * c;
+        self.baumgarte[1] = (beta / dt_sec) * qrr.xyz().dot(u);
+        self.baumgarte[2] = (beta / dt_sec) * qrr.xyz().dot(v);
+
+        // the limit only pushes back towards the allowed range, it's measured in sin(angle / 2)
+        // like the quaternion rows
+        let violated = self
+            .limits
+            .and_then(|(lower, upper)| exceeded_limit(self.angle, lower, upper));
+        if let Some((limit, lambda_min, lambda_max)) = violated {
+            set_jacobian_row(
+                &mut self.jacobian,
+                LIMIT_ROW,
+                Vec3::ZERO,
+                quat_jacobian(mat_a, hinge_axis),
+                Vec3::ZERO,
+                quat_jacobian(mat_b, hinge_axis),
+            );
+            self.lambda_min[LIMIT_ROW] = lambda_min;
+            self.lambda_max[LIMIT_ROW] = lambda_max;
+            let c = (self.angle * 0.5).sin() - (limit * 0.5).sin();
+            self.baumgarte[LIMIT_ROW] = (beta / dt_sec) * c;
+        } else {
+            self.cached_lambda[LIMIT_ROW] = 0.0;
+        }
 
-            // calculate the baumgarte stabilization
-            let c = r.dot(r);
-            let c = f32::max(0.0, c - 0.01);
-            const BETA: f32 = 0.05;
-            self.baumgarte = (BETA / dt_sec) * c;
+        // the motor works on the relative angular velocity around the world space axis
+        if let Some(motor) = self.motor {
+            let axis = q1 * hinge_axis;
+            set_jacobian_row(
+                &mut self.jacobian,
+                MOTOR_ROW,
+                Vec3::ZERO,
+                -axis,
+                Vec3::ZERO,
+                axis,
+            );
+            let max_impulse = motor.max_torque.abs() * dt_sec;
+            self.lambda_min[MOTOR_ROW] = -max_impulse;
+            self.lambda_max[MOTOR_ROW] = max_impulse;
+            let (bias, softness) = motor.bias_and_softness(self.angle, dt_sec);
+            self.baumgarte[MOTOR_ROW] = bias;
+            self.motor_softness = softness;
+        } else {
+            self.cached_lambda[MOTOR_ROW] = 0.0;
+            self.motor_softness = 0.0;
         }
+        self.dt_sec = dt_sec;
+
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
+    fn solve(&mut self, bodies: &ConstraintBodies) {
         let jacobian_transpose = self.jacobian.transpose();
 
         // build the system of equations
         let q_dt = self.config.get_velocities(bodies);
         let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
         let mut rhs = self.jacobian * q_dt * -1.0;
-        rhs[0] -= self.baumgarte;
+        for row in 0..5 {
+            rhs[row] -= self.baumgarte[row];
+        }
+
+        // a soft joint gives on every row but the motor's
+        soften_rows(
+            &mut j_w_jt,
+            &mut rhs,
+            &self.cached_lambda,
+            0..MOTOR_ROW,
+            self.joint_softness,
+        );
+
+        // a servo motor is soft, it gives more the more impulse it's already applying
+        j_w_jt.rows[MOTOR_ROW][MOTOR_ROW] += self.motor_softness;
+        rhs[MOTOR_ROW] -= self.motor_softness * self.cached_lambda[MOTOR_ROW];
 
         // solve for the Lagrange multipliers
-        let mut lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
-
-        // clamp the torque from the angle constraint
-        // we need to make sure it's a restorative torque
-        if self.is_angle_violated {
-            if self.relative_angle > 0.0 {
-                lambda_n[3] = f32::min(0.0, lambda_n[3]);
-            } else if self.relative_angle < 0.0 {
-                lambda_n[3] = f32::max(0.0, lambda_n[3]);
-            }
+        let mut lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
+
+        // accumulate the impulses and clamp the limit and motor rows to what they can apply
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        for row in LIMIT_ROW..5 {
+            self.cached_lambda[row] =
+                self.cached_lambda[row].clamp(self.lambda_min[row], self.lambda_max[row]);
         }
+        lambda_n = self.cached_lambda - old_lambda;
 
         // apply the impulses
         let impulses = jacobian_transpose * lambda_n;
         self.config.apply_impulses(bodies, impulses);
 
-        // accumulate the impulses for warm starting
-        self.cached_lambda += lambda_n;
+        if let Some(motor) = self.motor.as_mut() {
+            motor.set_applied_impulse(self.cached_lambda[MOTOR_ROW], self.dt_sec);
+        }
     }
 
     fn post_solve(&mut self) {
@@ -401,13 +302,98 @@ impl Constraint for ConstraintHingeQuatLimited {
                 *cached_lambda = 0.0
             }
 
-            const LIMIT: f32 = 20.0;
-            if *cached_lambda > LIMIT {
-                *cached_lambda = LIMIT;
-            }
-            if *cached_lambda < -LIMIT {
-                *cached_lambda = -LIMIT;
-            }
+            const LIMIT: f32 = 1e5;
+            *cached_lambda = cached_lambda.clamp(-LIMIT, LIMIT);
+        }
+    }
+
+    fn break_limits(&self) -> Option<BreakLimits> {
+        self.break_limits
+    }
+
+    fn applied_impulse(&self) -> (f32, f32) {
+        applied_impulse(&self.jacobian, &self.cached_lambda)
+    }
+
+    fn needs_wake(&self) -> bool {
+        self.motor.map_or(false, |motor| motor.needs_wake())
+    }
+
+    fn set_woken(&mut self) {
+        if let Some(motor) = self.motor.as_mut() {
+            motor.set_woken();
         }
     }
 }
+
+#[test]
+fn test_motor_change_wakes_bodies() {
+    use super::{wake_system, MotorTarget};
+    use crate::primitives::{Body, Sleeping};
+
+    let mut world = World::default();
+    let sleeping = Sleeping {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+    };
+    let a = world
+        .spawn()
+        .insert_bundle((Body::default(), sleeping))
+        .id();
+    let b = world
+        .spawn()
+        .insert_bundle((Body::default(), sleeping))
+        .id();
+    let joint = world
+        .spawn()
+        .insert(
+            HingeJoint::new(a, b, Vec3::ZERO, Vec3::Z)
+                .with_motor(AngularMotor::velocity(1.0, 10.0)),
+        )
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(wake_system::<HingeJoint>);
+
+    // nothing changed since the motor was made, they keep sleeping
+    stage.run(&mut world);
+    assert!(world.get::<Sleeping>(a).is_some());
+    assert!(world.get::<Sleeping>(b).is_some());
+
+    let mut hinge = world.get_mut::<HingeJoint>(joint).unwrap();
+    hinge.motor.as_mut().unwrap().target = MotorTarget::Velocity(-1.0);
+    stage.run(&mut world);
+    assert!(world.get::<Sleeping>(a).is_none());
+    assert!(world.get::<Sleeping>(b).is_none());
+}
+
+#[test]
+fn test_soft_limit_ignores_mass() {
+    use super::{test_anchor, test_body, test_constraint_step, test_constraint_world};
+    use crate::primitives::Body;
+
+    // the same hinge past its limit on a light and a heavy body
+    let push_back = |inv_mass: f32| {
+        let mut world = test_constraint_world();
+        let anchor = test_anchor(&mut world);
+        let body = test_body(&mut world, Vec3::ZERO, Vec3::ZERO);
+        world.get_mut::<Body>(body).unwrap().inv_mass = inv_mass;
+        world.spawn().insert(
+            HingeJoint::new(anchor, body, Vec3::ZERO, Vec3::Z)
+                .with_limits(-0.1, 0.1)
+                .with_frequency(2.0, 0.5),
+        );
+
+        // the first step takes the rest pose, then twist it past the upper limit
+        test_constraint_step::<HingeJoint>(&mut world);
+        world.get_mut::<GlobalTransform>(body).unwrap().rotation = Quat::from_rotation_z(0.3);
+        test_constraint_step::<HingeJoint>(&mut world);
+        world.get::<Body>(body).unwrap().angular_velocity
+    };
+
+    // the spring is scaled by the mass it moves, so both turn back just as fast
+    let light = push_back(1.0);
+    let heavy = push_back(0.1);
+    assert!(light.z < -0.1);
+    assert!((light - heavy).length() < light.length() * 1e-3);
+}
@@ -1,213 +1,278 @@
-use super::{quat_left, quat_right, Constraint, ConstraintConfig};
-use crate::{
-    body::Body,
-    math::{lcp_gauss_seidel, MatMN, MatN, VecN},
-};
 use bevy::prelude::*;
+use std::f32::consts::{PI, TAU};
+
+use super::{set_jacobian_row, soft_coefficients, Constraint, ConstraintBodies, ConstraintConfig};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
+
+/// What an angular motor is driving towards
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MotorTarget {
+    /// Relative angular velocity around the joint axis, in radians per second
+    Velocity(f32),
+    /// Relative angle around the joint axis, in radians, reached with a PD servo
+    Angle(f32),
+}
 
-pub struct ConstraintMotor {
-    config: ConstraintConfig,
-    jacobian: MatMN<4, 12>,
-    q0: Quat,
-    motor_axis: Vec3,
-    baumgarte: Vec3,
-    motor_speed: f32,
+/// Drives a joint around its axis, gameplay can change the target and max torque every frame.
+/// Changing the target or max torque wakes the joint's bodies. A [`super::HingeJoint`] can hold
+/// one, a [`MotorJoint`] drives any two bodies with one.
+#[derive(Copy, Clone, Debug)]
+pub struct AngularMotor {
+    pub target: MotorTarget,
+    pub max_torque: f32,
+    /// Servo torque per radian of error, only used when targeting an angle
+    pub stiffness: f32,
+    /// Servo torque per radian per second of relative velocity, only used when targeting an angle
+    pub damping: f32,
+
+    applied_torque: f32,
+    // the target and max torque the bodies were last woken for
+    woken: (MotorTarget, f32),
 }
 
-impl ConstraintMotor {
-    pub fn new(config: ConstraintConfig, q0: Quat, motor_axis: Vec3, motor_speed: f32) -> Self {
+impl AngularMotor {
+    pub fn velocity(target_speed: f32, max_torque: f32) -> Self {
         Self {
-            config,
-            jacobian: MatMN::zero(),
-            q0,
-            motor_axis,
-            motor_speed,
-            baumgarte: Vec3::ZERO,
+            target: MotorTarget::Velocity(target_speed),
+            max_torque,
+            stiffness: 0.0,
+            damping: 0.0,
+            applied_torque: 0.0,
+            woken: (MotorTarget::Velocity(target_speed), max_torque),
         }
     }
-}
 
-impl Constraint for ConstraintMotor {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            // get the orientation information of the bodies
-            let q1 = trans_a.rotation;
-            let q2 = trans_b.rotation;
-            let q0_inv = self.q0.inverse();
-            let q1_inv = q1.inverse();
-
-            // the axis is defined in the local space of body_a
-            let motor_axis = trans_a.rotation * self.motor_axis;
-            let (motor_u, motor_v) = motor_axis.any_orthonormal_pair();
-
-            let u = motor_u;
-            let v = motor_v;
-            let w = motor_axis;
-
-            let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
-            let p_t = p.transpose(); // pointless but self documenting
-
-            let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
-            let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
-
-            self.jacobian = MatMN::zero();
-
-            // first row is primary distance constraint that holds the anchor points together
-            {
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
+    pub fn servo(target_angle: f32, max_torque: f32, stiffness: f32, damping: f32) -> Self {
+        Self {
+            target: MotorTarget::Angle(target_angle),
+            max_torque,
+            stiffness,
+            damping,
+            applied_torque: 0.0,
+            woken: (MotorTarget::Angle(target_angle), max_torque),
+        }
+    }
 
-            // the quaternion jacobians
-            const IDX: usize = 1;
-
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[1][0] = j1.x;
-                self.jacobian.rows[1][1] = j1.y;
-                self.jacobian.rows[1][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, u));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][3] = j2.x;
-                self.jacobian.rows[1][4] = j2.y;
-                self.jacobian.rows[1][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[1][6] = j3.x;
-                self.jacobian.rows[1][7] = j3.y;
-                self.jacobian.rows[1][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, u));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][9] = j4.x;
-                self.jacobian.rows[1][10] = j4.y;
-                self.jacobian.rows[1][11] = j4.z;
-            }
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[2][0] = j1.x;
-                self.jacobian.rows[2][1] = j1.y;
-                self.jacobian.rows[2][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, v));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][3] = j2.x;
-                self.jacobian.rows[2][4] = j2.y;
-                self.jacobian.rows[2][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[2][6] = j3.x;
-                self.jacobian.rows[2][7] = j3.y;
-                self.jacobian.rows[2][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, v));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][9] = j4.x;
-                self.jacobian.rows[2][10] = j4.y;
-                self.jacobian.rows[2][11] = j4.z;
-            }
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[3][0] = j1.x;
-                self.jacobian.rows[3][1] = j1.y;
-                self.jacobian.rows[3][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, w));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][3] = j2.x;
-                self.jacobian.rows[3][4] = j2.y;
-                self.jacobian.rows[3][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[3][6] = j3.x;
-                self.jacobian.rows[3][7] = j3.y;
-                self.jacobian.rows[3][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, w));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][9] = j4.x;
-                self.jacobian.rows[3][10] = j4.y;
-                self.jacobian.rows[3][11] = j4.z;
+    /// Torque the motor applied on the last substep, signed around the joint axis
+    pub fn applied_torque(&self) -> f32 {
+        self.applied_torque
+    }
+
+    // Whether the target or max torque has changed since the bodies were last woken for it
+    pub(super) fn needs_wake(&self) -> bool {
+        self.woken != (self.target, self.max_torque)
+    }
+
+    pub(super) fn set_woken(&mut self) {
+        self.woken = (self.target, self.max_torque);
+    }
+
+    // The bias and softness of the motor row for the joint's current angle. A velocity target is a
+    // rigid velocity constraint, an angle target is an implicit spring towards the angle.
+    pub(super) fn bias_and_softness(&self, angle: f32, dt_sec: f32) -> (f32, f32) {
+        match self.target {
+            MotorTarget::Velocity(speed) => (-speed, 0.0),
+            MotorTarget::Angle(target) => {
+                let (softness, erp) = soft_coefficients(self.stiffness, self.damping, dt_sec);
+                let error = (angle - target + PI).rem_euclid(TAU) - PI;
+                (erp / dt_sec * error, softness)
             }
+        }
+    }
 
-            // calculate the baumgarte stabilization
-            let beta = 0.05;
-            let c = r.dot(r);
+    pub(super) fn set_applied_impulse(&mut self, impulse: f32, dt_sec: f32) {
+        self.applied_torque = impulse / dt_sec;
+    }
+}
+
+/// Drives body_b around an axis relative to body_a with an [`AngularMotor`], and nothing else. Put
+/// it next to another joint, like a [`super::ConeTwistJoint`], to drive that joint. The axis is
+/// given in world space, the joint takes the bodies' poses on the first step it runs as its rest
+/// pose.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct MotorJoint {
+    config: ConstraintConfig,
+    world_axis: Vec3,
+    initialized: bool,
+    // the initial relative quaternion q1^-1 * q2
+    q0: Quat,
 
-            let qr = trans_a.rotation.inverse() * trans_b.rotation;
-            let qr_a = qr * q0_inv; // relative orientation in body_a's space
+    pub motor: AngularMotor,
 
-            // get the world space axis for the relative rotation
-            let axis_a = trans_a.rotation * qr_a.xyz();
+    jacobian: MatMN<1, 12>,
+    cached_lambda: VecN<1>,
+    max_impulse: f32,
+    baumgarte: f32,
+    motor_softness: f32,
+    angle: f32,
+    dt_sec: f32,
+}
 
-            self.baumgarte[0] = (beta / dt_sec) * c;
-            self.baumgarte[1] = motor_u.dot(axis_a) * (beta / dt_sec);
-            self.baumgarte[2] = motor_v.dot(axis_a) * (beta / dt_sec);
+impl MotorJoint {
+    pub fn new(body_a: Entity, body_b: Entity, axis: Vec3, motor: AngularMotor) -> Self {
+        Self {
+            config: ConstraintConfig {
+                handle_a: body_a,
+                handle_b: body_b,
+                ..ConstraintConfig::default()
+            },
+            world_axis: axis.normalize(),
+            initialized: false,
+            q0: Quat::IDENTITY,
+            motor,
+            jacobian: MatMN::zero(),
+            cached_lambda: VecN::zero(),
+            max_impulse: 0.0,
+            baumgarte: 0.0,
+            motor_softness: 0.0,
+            angle: 0.0,
+            dt_sec: 0.0,
         }
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
-        let (_, _body_a, trans_a) = bodies.get_mut(self.config.handle_a).unwrap();
+    /// Angle of body_b relative to its rest pose around the axis, in radians
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+}
+
+impl Constraint for MotorJoint {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
+
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        if !self.initialized {
+            let (config, q0) = ConstraintConfig::from_world(
+                bodies,
+                self.config.handle_a,
+                self.config.handle_b,
+                Vec3::ZERO,
+                self.world_axis,
+            );
+            self.config = config;
+            self.q0 = q0;
+            self.initialized = true;
+        }
 
-        let motor_axis = trans_a.rotation * self.motor_axis;
+        let (_, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (_, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
 
-        let mut w_dt = VecN::zero();
-        w_dt[3] = motor_axis[0] * -self.motor_speed;
-        w_dt[4] = motor_axis[1] * -self.motor_speed;
-        w_dt[5] = motor_axis[2] * -self.motor_speed;
-        w_dt[9] = motor_axis[0] * self.motor_speed;
-        w_dt[10] = motor_axis[1] * self.motor_speed;
-        w_dt[11] = motor_axis[2] * self.motor_speed;
+        // q0 and -q0 are the same rotation so pick the one that keeps the relative quaternion's w
+        // positive
+        let q1_inv = trans_a.rotation.inverse();
+        let q2 = trans_b.rotation;
+        let mut q0_inv = self.q0.inverse();
+        if (q1_inv * q2 * q0_inv).w < 0.0 {
+            q0_inv = -q0_inv;
+        }
+        let qrr = q1_inv * q2 * q0_inv;
+        self.angle = 2.0 * qrr.xyz().dot(self.config.axis_a).atan2(qrr.w);
+
+        // a single row on the relative angular velocity around the world space axis
+        let axis = trans_a.rotation * self.config.axis_a;
+        self.jacobian = MatMN::zero();
+        set_jacobian_row(&mut self.jacobian, 0, Vec3::ZERO, -axis, Vec3::ZERO, axis);
+
+        self.max_impulse = self.motor.max_torque.abs() * dt_sec;
+        let (bias, softness) = self.motor.bias_and_softness(self.angle, dt_sec);
+        self.baumgarte = bias;
+        self.motor_softness = softness;
+        self.dt_sec = dt_sec;
+
+        // the max torque may have dropped since last frame
+        self.cached_lambda[0] = self.cached_lambda[0].clamp(-self.max_impulse, self.max_impulse);
+
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
+    }
 
+    fn solve(&mut self, bodies: &ConstraintBodies) {
         let jacobian_transpose = self.jacobian.transpose();
 
         // build the system of equations
-        let q_dt = self.config.get_velocities(bodies) - w_dt; // by subtracting by the desired velocity, the solver is tricked into applying the impulse to give us that velocity
+        let q_dt = self.config.get_velocities(bodies);
         let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
         let mut rhs = self.jacobian * q_dt * -1.0;
-        rhs[0] -= self.baumgarte[0];
-        rhs[1] -= self.baumgarte[1];
-        rhs[2] -= self.baumgarte[2];
+        rhs[0] -= self.baumgarte;
+
+        // a servo motor is soft, it gives more the more impulse it's already applying
+        j_w_jt.rows[0][0] += self.motor_softness;
+        rhs[0] -= self.motor_softness * self.cached_lambda[0];
 
-        // solve for the lagrange multipliers
-        let lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
+        // solve for the Lagrange multipliers
+        let mut lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
+
+        // accumulate the impulses and clamp to what the motor can apply
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        self.cached_lambda[0] = self.cached_lambda[0].clamp(-self.max_impulse, self.max_impulse);
+        lambda_n = self.cached_lambda - old_lambda;
 
         // apply the impulses
         let impulses = jacobian_transpose * lambda_n;
         self.config.apply_impulses(bodies, impulses);
+
+        self.motor
+            .set_applied_impulse(self.cached_lambda[0], self.dt_sec);
+    }
+
+    fn post_solve(&mut self) {
+        if !self.cached_lambda[0].is_finite() {
+            self.cached_lambda[0] = 0.0
+        }
     }
+
+    fn needs_wake(&self) -> bool {
+        self.motor.needs_wake()
+    }
+
+    fn set_woken(&mut self) {
+        self.motor.set_woken();
+    }
+}
+
+#[test]
+fn test_motor_joint_limited_by_max_torque() {
+    use super::{test_anchor, test_body, test_constraint_step, test_constraint_world};
+    use crate::primitives::Body;
+
+    let mut world = test_constraint_world();
+    let anchor = test_anchor(&mut world);
+
+    // a unit inertia body driven to spin at 2 rad/s around z
+    let free = test_body(&mut world, Vec3::X, Vec3::ZERO);
+    let free_motor = world
+        .spawn()
+        .insert(MotorJoint::new(
+            anchor,
+            free,
+            Vec3::Z,
+            AngularMotor::velocity(2.0, 100.0),
+        ))
+        .id();
+
+    // 1 N m over the 0.1s substep can only get it to 0.1 rad/s
+    let weak = test_body(&mut world, Vec3::X, Vec3::ZERO);
+    let weak_motor = world
+        .spawn()
+        .insert(MotorJoint::new(
+            anchor,
+            weak,
+            Vec3::Z,
+            AngularMotor::velocity(2.0, 1.0),
+        ))
+        .id();
+
+    test_constraint_step::<MotorJoint>(&mut world);
+
+    let spin = |e| world.get::<Body>(e).unwrap().angular_velocity;
+    assert!((spin(free) - Vec3::Z * 2.0).length() < 1e-4);
+    assert!((spin(weak) - Vec3::Z * 0.1).length() < 1e-4);
+
+    let torque = |e| world.get::<MotorJoint>(e).unwrap().motor.applied_torque();
+    assert!((torque(free_motor) - 20.0).abs() < 1e-2);
+    assert!((torque(weak_motor) - 1.0).abs() < 1e-4);
 }
@@ -1,27 +1,217 @@
 use bevy::prelude::*;
 
-use crate::prelude::Body;
+use super::{
+    applied_impulse, set_jacobian_row, soft_coefficients, BreakLimits, Constraint,
+    ConstraintBodies, ConstraintConfig,
+};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
 
-use super::{Constraint, ConstraintConfig};
-
-pub struct ConstraintMoverSimple {
+/// Pulls a point on a body towards a target like a spring, for grabbing and dragging bodies with
+/// the mouse or a hand. Gameplay moves the target every frame, the body follows as far as the max
+/// force allows. The anchor is in the body's space, relative to its centre of mass. A new follow or
+/// a moved target wakes the body.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct TargetFollow {
     config: ConstraintConfig,
-    time: f32,
+
+    /// World space point the anchor is pulled towards
+    pub target: Vec3,
+    /// World space orientation the body is turned towards, None leaves it free to rotate
+    pub target_rotation: Option<Quat>,
+    /// Spring force per meter from the target, the same spring turns the body
+    pub stiffness: f32,
+    pub damping: f32,
+    pub max_force: f32,
+    pub max_torque: f32,
+    pub break_limits: Option<BreakLimits>,
+
+    jacobian: MatMN<6, 12>,
+    cached_lambda: VecN<6>,
+    baumgarte: VecN<6>,
+    softness: f32,
+    max_impulse: f32,
+    max_angular_impulse: f32,
+    // the targets the body was last woken for
+    woken: (Vec3, Option<Quat>),
 }
 
-impl ConstraintMoverSimple {
-    pub fn new(config: ConstraintConfig) -> Self {
-        ConstraintMoverSimple { config, time: 0.0 }
+impl TargetFollow {
+    pub fn new(body: Entity, anchor: Vec3, target: Vec3) -> Self {
+        Self {
+            // a single body, body_b's columns of the jacobian are always zero
+            config: ConstraintConfig {
+                handle_a: body,
+                handle_b: body,
+                anchor_a: anchor,
+                ..ConstraintConfig::default()
+            },
+            target,
+            target_rotation: None,
+            stiffness: 100.0,
+            damping: 10.0,
+            max_force: 1000.0,
+            max_torque: 1000.0,
+            break_limits: None,
+            jacobian: MatMN::zero(),
+            cached_lambda: VecN::zero(),
+            baumgarte: VecN::zero(),
+            softness: 0.0,
+            max_impulse: 0.0,
+            max_angular_impulse: 0.0,
+            // never equal to a target, so a new follow wakes its body
+            woken: (Vec3::splat(f32::NAN), None),
+        }
+    }
+
+    pub fn with_rotation(mut self, target_rotation: Quat) -> Self {
+        self.target_rotation = Some(target_rotation);
+        self
     }
+
+    pub fn with_spring(mut self, stiffness: f32, damping: f32) -> Self {
+        self.stiffness = stiffness;
+        self.damping = damping;
+        self
+    }
+
+    pub fn with_max_force(mut self, max_force: f32, max_torque: f32) -> Self {
+        self.max_force = max_force;
+        self.max_torque = max_torque;
+        self
+    }
+
+    /// Breaks the joint once it has to hold more than the force or torque
+    pub fn with_break_limits(mut self, force: f32, torque: f32) -> Self {
+        self.break_limits = Some(BreakLimits { force, torque });
+        self
+    }
+
+    pub fn body(&self) -> Entity {
+        self.config.handle_a
+    }
+}
+
+// Clamps the length of three accumulated impulses to the max
+fn clamp_length(lambda: &mut VecN<6>, start: usize, max: f32) {
+    let v = Vec3::from_slice(&lambda[start..start + 3]).clamp_length_max(max);
+    lambda[start] = v.x;
+    lambda[start + 1] = v.y;
+    lambda[start + 2] = v.z;
 }
 
-impl Constraint for ConstraintMoverSimple {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        self.time += dt_sec;
-        let (_, mut body_a, _trans_a) = bodies.get_mut(self.config.handle_a).unwrap();
+impl Constraint for TargetFollow {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
+
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        let (body, trans, _) = bodies.get(self.config.handle_a).unwrap();
+
+        let world_anchor = body.local_to_world(&trans, self.config.anchor_a);
+        let r = world_anchor - body.centre_of_mass_world(&trans);
+        let error = world_anchor - self.target;
+
+        let (softness, erp) = soft_coefficients(self.stiffness, self.damping, dt_sec);
+        self.softness = softness;
+        self.max_impulse = self.max_force.abs() * dt_sec;
+        self.max_angular_impulse = self.max_torque.abs() * dt_sec;
 
-        body_a.linear_velocity.z = f32::cos(self.time * 0.25) * 4.0;
+        self.jacobian = MatMN::zero();
+        self.baumgarte = VecN::zero();
+
+        // the first three rows move the anchor towards the target along each world axis
+        for (row, axis) in [(0, Vec3::X), (1, Vec3::Y), (2, Vec3::Z)] {
+            set_jacobian_row(
+                &mut self.jacobian,
+                row,
+                axis,
+                r.cross(axis),
+                Vec3::ZERO,
+                Vec3::ZERO,
+            );
+            self.baumgarte[row] = (erp / dt_sec) * error.dot(axis);
+        }
+
+        // the last three turn the body through the smallest rotation to the target orientation
+        match self.target_rotation {
+            Some(target_rotation) => {
+                let mut q_err = trans.rotation * target_rotation.inverse();
+                if q_err.w < 0.0 {
+                    q_err = -q_err;
+                }
+                let angular_error = q_err.xyz() * 2.0;
+                for (row, axis) in [(3, Vec3::X), (4, Vec3::Y), (5, Vec3::Z)] {
+                    set_jacobian_row(
+                        &mut self.jacobian,
+                        row,
+                        Vec3::ZERO,
+                        axis,
+                        Vec3::ZERO,
+                        Vec3::ZERO,
+                    );
+                    self.baumgarte[row] = (erp / dt_sec) * angular_error.dot(axis);
+                }
+            }
+            None => self.cached_lambda[3..].fill(0.0),
+        }
+
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
+    }
+
+    fn solve(&mut self, bodies: &ConstraintBodies) {
+        let jacobian_transpose = self.jacobian.transpose();
+
+        // build the system of equations
+        let q_dt = self.config.get_velocities(bodies);
+        let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
+        let mut rhs = self.jacobian * q_dt * -1.0;
+
+        // the spring is soft, it gives more the more impulse it's already applying
+        for row in 0..6 {
+            j_w_jt.rows[row][row] += self.softness;
+            rhs[row] -= self.baumgarte[row] + self.softness * self.cached_lambda[row];
+        }
+
+        // solve for the Lagrange multipliers
+        let mut lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
+
+        // accumulate the impulses and keep the total within the max force and torque
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        clamp_length(&mut self.cached_lambda, 0, self.max_impulse);
+        clamp_length(&mut self.cached_lambda, 3, self.max_angular_impulse);
+        lambda_n = self.cached_lambda - old_lambda;
+
+        // apply the impulses
+        let impulses = jacobian_transpose * lambda_n;
+        self.config.apply_impulses(bodies, impulses);
     }
 
-    fn solve(&mut self, _bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {}
+    fn post_solve(&mut self) {
+        // limit the warm starting to reasonable limits
+        for cached_lambda in self.cached_lambda.iter_mut() {
+            if !cached_lambda.is_finite() {
+                *cached_lambda = 0.0
+            }
+        }
+    }
+
+    fn break_limits(&self) -> Option<BreakLimits> {
+        self.break_limits
+    }
+
+    fn applied_impulse(&self) -> (f32, f32) {
+        applied_impulse(&self.jacobian, &self.cached_lambda)
+    }
+
+    fn needs_wake(&self) -> bool {
+        self.woken != (self.target, self.target_rotation)
+    }
+
+    fn set_woken(&mut self) {
+        self.woken = (self.target, self.target_rotation);
+    }
 }
@@ -1,183 +1,204 @@
-use super::{quat_left, quat_right, Constraint, ConstraintConfig};
-use crate::{
-    math::{lcp_gauss_seidel, MatMN, MatN},
-    prelude::Body,
-};
 use bevy::prelude::*;
 
-pub struct ConstraintOrientation {
+use super::{
+    applied_impulse, quat_jacobian, quat_left, quat_right, set_jacobian_row, soft_erp, soften_rows,
+    BreakLimits, Constraint, ConstraintBodies, ConstraintConfig, SoftConstraint,
+};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
+
+/// Welds two bodies together, holding the relative position and orientation they have on the
+/// first step the joint runs. Remove the component to break them apart again.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct FixedJoint {
     config: ConstraintConfig,
+    initialized: bool,
+    // the initial relative quaternion q1^-1 * q2
     q0: Quat,
+
+    pub break_limits: Option<BreakLimits>,
+    /// Makes the joint springy, None holds it rigidly
+    pub soft: Option<SoftConstraint>,
+
     jacobian: MatMN<4, 12>,
-    baumgarte: f32,
+    cached_lambda: VecN<4>,
+    baumgarte: VecN<4>,
+    // the soft constraint's softness, as a multiple of each row's effective inverse mass
+    joint_softness: f32,
 }
 
-impl ConstraintOrientation {
-    pub fn new(config: ConstraintConfig, q0: Quat) -> Self {
-        ConstraintOrientation {
-            config,
-            q0,
+impl FixedJoint {
+    pub fn new(body_a: Entity, body_b: Entity) -> Self {
+        Self {
+            config: ConstraintConfig {
+                handle_a: body_a,
+                handle_b: body_b,
+                ..ConstraintConfig::default()
+            },
+            initialized: false,
+            q0: Quat::IDENTITY,
+            break_limits: None,
+            soft: None,
+            joint_softness: 0.0,
             jacobian: MatMN::zero(),
-            baumgarte: 0.0,
+            cached_lambda: VecN::zero(),
+            baumgarte: VecN::zero(),
         }
     }
+
+    /// Lets the joint give like a damped spring instead of holding rigidly
+    pub fn with_frequency(mut self, frequency: f32, damping_ratio: f32) -> Self {
+        self.soft = Some(SoftConstraint::new(frequency, damping_ratio));
+        self
+    }
+
+    /// Breaks the joint once it has to hold more than the force or torque
+    pub fn with_break_limits(mut self, force: f32, torque: f32) -> Self {
+        self.break_limits = Some(BreakLimits { force, torque });
+        self
+    }
 }
 
-impl Constraint for ConstraintOrientation {
-    fn pre_solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32) {
-        unsafe {
-            let (_, body_a, trans_a) = bodies.get_unchecked(self.config.handle_a).unwrap();
-            let (_, body_b, trans_b) = bodies.get_unchecked(self.config.handle_b).unwrap();
-
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
-
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
-
-            let r = world_anchor_b - world_anchor_a;
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            let a = world_anchor_a;
-            let b = world_anchor_b;
-
-            // get the orientation information of the bodies
-            let q1 = trans_a.rotation;
-            let q2 = trans_b.rotation;
-            let q0_inv = self.q0.inverse();
-            let q1_inv = q1.inverse();
-
-            let u = Vec3::X;
-            let v = Vec3::Y;
-            let w = Vec3::Z;
-
-            let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
-            let p_t = p.transpose(); // pointless but self documenting
-
-            let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
-            let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
-
-            // the distance constraint
-            self.jacobian = MatMN::zero();
-
-            {
-                // first row is the primary distance constraint that holds anchor points together
-                let j1 = (a - b) * 2.0;
-                self.jacobian.rows[0][0] = j1.x;
-                self.jacobian.rows[0][1] = j1.y;
-                self.jacobian.rows[0][2] = j1.z;
-
-                let j2 = ra.cross((a - b) * 2.0);
-                self.jacobian.rows[0][3] = j2.x;
-                self.jacobian.rows[0][4] = j2.y;
-                self.jacobian.rows[0][5] = j2.z;
-
-                let j3 = (b - a) * 2.0;
-                self.jacobian.rows[0][6] = j3.x;
-                self.jacobian.rows[0][7] = j3.y;
-                self.jacobian.rows[0][8] = j3.z;
-
-                let j4 = rb.cross((b - a) * 2.0);
-                self.jacobian.rows[0][9] = j4.x;
-                self.jacobian.rows[0][10] = j4.y;
-                self.jacobian.rows[0][11] = j4.z;
-            }
+impl Constraint for FixedJoint {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
 
-            const IDX: usize = 1;
-
-            // the quaternion jacobians
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[1][0] = j1.x;
-                self.jacobian.rows[1][1] = j1.y;
-                self.jacobian.rows[1][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, u));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][3] = j2.x;
-                self.jacobian.rows[1][4] = j2.y;
-                self.jacobian.rows[1][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[1][6] = j3.x;
-                self.jacobian.rows[1][7] = j3.y;
-                self.jacobian.rows[1][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, u));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[1][9] = j4.x;
-                self.jacobian.rows[1][10] = j4.y;
-                self.jacobian.rows[1][11] = j4.z;
-            }
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[2][0] = j1.x;
-                self.jacobian.rows[2][1] = j1.y;
-                self.jacobian.rows[2][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, v));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][3] = j2.x;
-                self.jacobian.rows[2][4] = j2.y;
-                self.jacobian.rows[2][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[2][6] = j3.x;
-                self.jacobian.rows[2][7] = j3.y;
-                self.jacobian.rows[2][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, v));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[2][9] = j4.x;
-                self.jacobian.rows[2][10] = j4.y;
-                self.jacobian.rows[2][11] = j4.z;
-            }
-            {
-                let j1 = Vec3::ZERO;
-                self.jacobian.rows[3][0] = j1.x;
-                self.jacobian.rows[3][1] = j1.y;
-                self.jacobian.rows[3][2] = j1.z;
-
-                let tmp = mat_a * Vec4::from((0.0, w));
-                let j2 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][3] = j2.x;
-                self.jacobian.rows[3][4] = j2.y;
-                self.jacobian.rows[3][5] = j2.z;
-
-                let j3 = Vec3::ZERO;
-                self.jacobian.rows[3][6] = j3.x;
-                self.jacobian.rows[3][7] = j3.y;
-                self.jacobian.rows[3][8] = j3.z;
-
-                let tmp = mat_b * Vec4::from((0.0, w));
-                let j4 = Vec3::new(tmp[IDX + 0], tmp[IDX + 1], tmp[IDX + 2]);
-                self.jacobian.rows[3][9] = j4.x;
-                self.jacobian.rows[3][10] = j4.y;
-                self.jacobian.rows[3][11] = j4.z;
-            }
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        // the anchor sits halfway between the bodies, where the glue is
+        if !self.initialized {
+            let (_, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+            let (_, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+            let world_anchor = (trans_a.translation + trans_b.translation) * 0.5;
+            let (config, q0) = ConstraintConfig::from_world(
+                bodies,
+                self.config.handle_a,
+                self.config.handle_b,
+                world_anchor,
+                Vec3::ZERO,
+            );
+            self.config = config;
+            self.q0 = q0;
+            self.initialized = true;
+        }
 
-            // calculate the baumgarte stabilization
-            let c = r.dot(r);
-            const BETA: f32 = 0.5;
-            self.baumgarte = (BETA / dt_sec) * c;
+        let (body_a, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+
+        // get the world space position of the anchor from body_a's orientation
+        let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
+
+        // get the world space position of the anchor from body_b's orientation
+        let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
+
+        let r = world_anchor_b - world_anchor_a;
+        let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
+        let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
+        let a = world_anchor_a;
+        let b = world_anchor_b;
+
+        // get the orientation information of the bodies, q0 and -q0 are the same rotation so
+        // pick the one that keeps the relative quaternion's w positive
+        let q1 = trans_a.rotation;
+        let q2 = trans_b.rotation;
+        let q1_inv = q1.inverse();
+        let mut q0_inv = self.q0.inverse();
+        if (q1_inv * q2 * q0_inv).w < 0.0 {
+            q0_inv = -q0_inv;
+        }
+        let qrr = q1_inv * q2 * q0_inv;
+
+        let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
+        let p_t = p.transpose(); // pointless but self documenting
+
+        let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
+        let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
+
+        self.jacobian = MatMN::zero();
+
+        // first row is the primary distance constraint that holds anchor points together
+        set_jacobian_row(
+            &mut self.jacobian,
+            0,
+            (a - b) * 2.0,
+            ra.cross((a - b) * 2.0),
+            (b - a) * 2.0,
+            rb.cross((b - a) * 2.0),
+        );
+
+        // the quaternion jacobians hold the relative orientation on every axis
+        const BETA: f32 = 0.05;
+        let (beta, softness) = soft_erp(self.soft, BETA, dt_sec);
+        self.joint_softness = softness;
+        for (row, axis) in [(1, Vec3::X), (2, Vec3::Y), (3, Vec3::Z)] {
+            set_jacobian_row(
+                &mut self.jacobian,
+                row,
+                Vec3::ZERO,
+                quat_jacobian(mat_a, axis),
+                Vec3::ZERO,
+                quat_jacobian(mat_b, axis),
+            );
+            self.baumgarte[row] = (beta / dt_sec) * qrr.xyz().dot(axis);
         }
+
+        // calculate the baumgarte stabilization
+        let c = f32::max(0.0, r.dot(r) - 0.0001);
+        self.baumgarte[0] = (beta / dt_sec) * c;
+
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
     }
 
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>) {
+    fn solve(&mut self, bodies: &ConstraintBodies) {
         let jacobian_transpose = self.jacobian.transpose();
 
         // build the system of equations
         let q_dt = self.config.get_velocities(bodies);
         let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
-        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
         let mut rhs = self.jacobian * q_dt * -1.0;
-        rhs[0] -= self.baumgarte;
+        for row in 0..4 {
+            rhs[row] -= self.baumgarte[row];
+        }
+
+        // a soft joint gives like a spring on every row
+        soften_rows(
+            &mut j_w_jt,
+            &mut rhs,
+            &self.cached_lambda,
+            0..4,
+            self.joint_softness,
+        );
 
         // solve for the Lagrange multipliers
-        let lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
+        let lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
 
         // apply the impulses
         let impulses = jacobian_transpose * lambda_n;
         self.config.apply_impulses(bodies, impulses);
+
+        // accumulate the impulses for warm starting
+        self.cached_lambda += lambda_n;
+    }
+
+    fn post_solve(&mut self) {
+        // limit the warm starting to reasonable limits
+        for cached_lambda in self.cached_lambda.iter_mut() {
+            if !cached_lambda.is_finite() {
+                *cached_lambda = 0.0
+            }
+
+            const LIMIT: f32 = 1e5;
+            *cached_lambda = cached_lambda.clamp(-LIMIT, LIMIT);
+        }
+    }
+
+    fn break_limits(&self) -> Option<BreakLimits> {
+        self.break_limits
+    }
+
+    fn applied_impulse(&self) -> (f32, f32) {
+        applied_impulse(&self.jacobian, &self.cached_lambda)
     }
 }
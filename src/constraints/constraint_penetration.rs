@@ -1,11 +1,7 @@
 use bevy::prelude::*;
 
-use super::ConstraintConfig;
-use crate::{
-    math::{lcp_gauss_seidel, MatMN, MatN, VecN},
-    primitives::*,
-    PhysicsTime,
-};
+use super::{set_jacobian_row, Constraint, ConstraintBodies, ConstraintConfig};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
 
 #[derive(Component, Copy, Clone, Debug)]
 pub struct ConstraintPenetration {
@@ -39,205 +35,108 @@ impl ConstraintPenetration {
     }
 }
 
-pub fn pre_solve_system(
-    mut commands: Commands,
-    pt: Res<PhysicsTime>,
-    mut query: Query<(Entity, &mut ConstraintPenetration)>,
-    mut bodies: Query<(&mut Body, &mut GlobalTransform)>,
-) {
-    for (e, mut constraint) in query.iter_mut() {
-        unsafe {
-            let a = bodies.get_unchecked(constraint.config.handle_a);
-            let b = bodies.get_unchecked(constraint.config.handle_a);
-            if a.is_err() || b.is_err() {
-                warn!("Pre Solve System Remove: {:?}", e);
-                commands.entity(e).despawn();
-                continue;
-            }
-            let (body_a, trans_a) = a.unwrap();
-            let (body_b, trans_b) = b.unwrap();
+impl Constraint for ConstraintPenetration {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
 
-            // get the world space position of the hinge from body_a's orientation
-            let world_anchor_a = body_a.local_to_world(&trans_a, constraint.config.anchor_a);
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        let (body_a, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
 
-            // get the world space position of the hinge from body_b's orientation
-            let world_anchor_b = body_b.local_to_world(&trans_b, constraint.config.anchor_b);
+        // get the world space position of the hinge from body_a's orientation
+        let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
 
-            let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
-            let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
-            constraint.friction = body_a.friction * body_b.friction;
+        // get the world space position of the hinge from body_b's orientation
+        let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
 
-            // should be equivalent to Vec3::GetOrtho() from the book
-            let (mut u, mut v) = constraint.normal.any_orthonormal_pair();
+        let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
+        let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
+        self.friction = body_a.friction * body_b.friction;
 
-            // convert tangent space from model space to world space
-            let normal = trans_a.rotation * constraint.normal;
-            u = trans_a.rotation * u;
-            v = trans_a.rotation * v;
+        // should be equivalent to Vec3::GetOrtho() from the book
+        let (mut u, mut v) = self.normal.any_orthonormal_pair();
 
-            // penetration constraint
-            constraint.jacobian = MatMN::zero();
+        // convert tangent space from model space to world space
+        let normal = trans_a.rotation * self.normal;
+        u = trans_a.rotation * u;
+        v = trans_a.rotation * v;
 
-            // first row is the primary distance constraint that holds the anchor points together
-            {
-                let j1 = -normal;
-                constraint.jacobian.rows[0][0] = j1.x;
-                constraint.jacobian.rows[0][1] = j1.y;
-                constraint.jacobian.rows[0][2] = j1.z;
-            }
-
-            {
-                let j2 = ra.cross(-normal);
-                constraint.jacobian.rows[0][3] = j2.x;
-                constraint.jacobian.rows[0][4] = j2.y;
-                constraint.jacobian.rows[0][5] = j2.z;
-            }
-
-            {
-                let j3 = normal;
-                constraint.jacobian.rows[0][6] = j3.x;
-                constraint.jacobian.rows[0][7] = j3.y;
-                constraint.jacobian.rows[0][8] = j3.z;
-            }
-
-            {
-                let j4 = rb.cross(normal);
-                constraint.jacobian.rows[0][9] = j4.x;
-                constraint.jacobian.rows[0][10] = j4.y;
-                constraint.jacobian.rows[0][11] = j4.z;
-            }
+        // penetration constraint
+        self.jacobian = MatMN::zero();
 
-            // friction jacobians
-            if constraint.friction > 0.0 {
-                {
-                    let j1 = -u;
-                    constraint.jacobian.rows[1][0] = j1.x;
-                    constraint.jacobian.rows[1][1] = j1.y;
-                    constraint.jacobian.rows[1][2] = j1.z;
-                }
-                {
-                    let j2 = ra.cross(-u);
-                    constraint.jacobian.rows[1][3] = j2.x;
-                    constraint.jacobian.rows[1][4] = j2.y;
-                    constraint.jacobian.rows[1][5] = j2.z;
-                }
-                {
-                    let j3 = u;
-                    constraint.jacobian.rows[1][6] = j3.x;
-                    constraint.jacobian.rows[1][7] = j3.y;
-                    constraint.jacobian.rows[1][8] = j3.z;
-                }
-                {
-                    let j4 = rb.cross(u);
-                    constraint.jacobian.rows[1][9] = j4.x;
-                    constraint.jacobian.rows[1][10] = j4.y;
-                    constraint.jacobian.rows[1][11] = j4.z;
-                }
-
-                {
-                    let j1 = -v;
-                    constraint.jacobian.rows[2][0] = j1.x;
-                    constraint.jacobian.rows[2][1] = j1.y;
-                    constraint.jacobian.rows[2][2] = j1.z;
-                }
-                {
-                    let j2 = ra.cross(-v);
-                    constraint.jacobian.rows[2][3] = j2.x;
-                    constraint.jacobian.rows[2][4] = j2.y;
-                    constraint.jacobian.rows[2][5] = j2.z;
-                }
-                {
-                    let j3 = v;
-                    constraint.jacobian.rows[2][6] = j3.x;
-                    constraint.jacobian.rows[2][7] = j3.y;
-                    constraint.jacobian.rows[2][8] = j3.z;
-                }
-                {
-                    let j4 = rb.cross(v);
-                    constraint.jacobian.rows[2][9] = j4.x;
-                    constraint.jacobian.rows[2][10] = j4.y;
-                    constraint.jacobian.rows[2][11] = j4.z;
-                }
+        // first row is the primary distance constraint that holds the anchor points together
+        set_jacobian_row(
+            &mut self.jacobian,
+            0,
+            -normal,
+            ra.cross(-normal),
+            normal,
+            rb.cross(normal),
+        );
+
+        // friction jacobians
+        if self.friction > 0.0 {
+            for (row, dir) in [(1, u), (2, v)] {
+                set_jacobian_row(
+                    &mut self.jacobian,
+                    row,
+                    -dir,
+                    ra.cross(-dir),
+                    dir,
+                    rb.cross(dir),
+                );
             }
+        }
 
-            // apply warm starting from last frame
-            let impulses = constraint.jacobian.transpose() * constraint.cached_lambda;
-            constraint.config.apply_impulses(&mut bodies, impulses);
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
 
-            // calculate the baumgarte stabilization
-            let mut c = (world_anchor_b - world_anchor_a).dot(normal);
-            c = f32::min(0.0, c + 0.02); // add slop
-            let beta = 0.25;
-            constraint.baumgarte = beta * c / pt.time;
-        }
+        // calculate the baumgarte stabilization
+        let mut c = (world_anchor_b - world_anchor_a).dot(normal);
+        c = f32::min(0.0, c + 0.02); // add slop
+        let beta = 0.25;
+        self.baumgarte = beta * c / dt_sec;
     }
-}
 
-pub fn solve_system(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut ConstraintPenetration)>,
-    mut bodies: Query<(&mut Body, &mut GlobalTransform)>,
-) {
-    for (e, mut constraint) in query.iter_mut() {
-        unsafe {
-            let a = bodies.get_unchecked(constraint.config.handle_a);
-            let b = bodies.get_unchecked(constraint.config.handle_a);
-            if a.is_err() || b.is_err() {
-                warn!("Solve System Remove: {:?}", e);
-                commands.entity(e).despawn();
-                continue;
-            }
-            let (body_a, _trans_a) = a.unwrap();
-            let (body_b, _trans_b) = b.unwrap();
-
-            let jacobian_transpose = constraint.jacobian.transpose();
-
-            // build the system of equations
-            let q_dt = constraint.config.get_velocities(&mut bodies);
-            let inv_mass_matrix = constraint.config.get_inverse_mass_matrix(&mut bodies);
-            let j_w_jt = constraint.jacobian * inv_mass_matrix * jacobian_transpose;
-            let mut rhs = constraint.jacobian * q_dt * -1.0;
-            rhs[0] -= constraint.baumgarte;
-
-            // solve for the Lagrange multipliers
-            let mut lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
-
-            // accumulate the impulses and clamp within the constraint limits
-            let old_lambda = constraint.cached_lambda;
-            constraint.cached_lambda += lambda_n;
-            let lambda_limit = 0.0;
-            if constraint.cached_lambda[0] < lambda_limit {
-                constraint.cached_lambda[0] = lambda_limit;
-            }
+    fn solve(&mut self, bodies: &ConstraintBodies) {
+        let (body_a, _trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, _trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+        let inv_mass_sum = body_a.effective_inv_mass() + body_b.effective_inv_mass();
+
+        let jacobian_transpose = self.jacobian.transpose();
+
+        // build the system of equations
+        let q_dt = self.config.get_velocities(bodies);
+        let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
+        let j_w_jt = self.jacobian * inv_mass_matrix * jacobian_transpose;
+        let mut rhs = self.jacobian * q_dt * -1.0;
+        rhs[0] -= self.baumgarte;
+
+        // solve for the Lagrange multipliers
+        let mut lambda_n = lcp_gauss_seidel(&MatN::from(j_w_jt), &rhs);
+
+        // accumulate the impulses and clamp within the constraint limits
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        let lambda_limit = 0.0;
+        if self.cached_lambda[0] < lambda_limit {
+            self.cached_lambda[0] = lambda_limit;
+        }
 
-            if constraint.friction > 0.0 {
-                let umg = constraint.friction * 10.0 * 1.0 / (body_a.inv_mass + body_b.inv_mass);
-                let normal_force = (lambda_n[0] * constraint.friction).abs();
-                let max_force = if umg > normal_force {
-                    umg
-                } else {
-                    normal_force
-                };
-
-                if constraint.cached_lambda[1] > max_force {
-                    constraint.cached_lambda[1] = max_force;
-                }
-                if constraint.cached_lambda[1] < -max_force {
-                    constraint.cached_lambda[1] = -max_force;
-                }
-
-                if constraint.cached_lambda[2] > max_force {
-                    constraint.cached_lambda[2] = max_force;
-                }
-                if constraint.cached_lambda[2] < -max_force {
-                    constraint.cached_lambda[2] = -max_force;
-                }
-            }
-            lambda_n = constraint.cached_lambda - old_lambda;
+        if self.friction > 0.0 {
+            let umg = self.friction * 10.0 * 1.0 / inv_mass_sum;
+            let normal_force = (lambda_n[0] * self.friction).abs();
+            let max_force = umg.max(normal_force);
 
-            // apply the impulses
-            let impulses = jacobian_transpose * lambda_n;
-            constraint.config.apply_impulses(&mut bodies, impulses);
+            self.cached_lambda[1] = self.cached_lambda[1].clamp(-max_force, max_force);
+            self.cached_lambda[2] = self.cached_lambda[2].clamp(-max_force, max_force);
         }
+        lambda_n = self.cached_lambda - old_lambda;
+
+        // apply the impulses
+        let impulses = jacobian_transpose * lambda_n;
+        self.config.apply_impulses(bodies, impulses);
     }
 }
@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+
+use super::{
+    applied_impulse, exceeded_limit, quat_jacobian, quat_left, quat_right, set_jacobian_row,
+    soft_erp, soften_rows, BreakLimits, Constraint, ConstraintBodies, ConstraintConfig,
+    SoftConstraint,
+};
+use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
+
+const ROWS: usize = 7;
+const LIMIT_ROW: usize = 5;
+const MOTOR_ROW: usize = 6;
+
+/// Drives a slider towards a target speed, without ever using more than the max force
+#[derive(Copy, Clone, Debug)]
+pub struct PrismaticMotor {
+    /// Speed of body_b relative to body_a along the axis
+    pub target_speed: f32,
+    pub max_force: f32,
+}
+
+/// Lets body_b only slide along an axis relative to body_a, for pistons, drawers and elevators.
+/// The anchor and axis are given in world space, the joint takes the bodies' poses on the first
+/// step it runs as its rest pose.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct PrismaticJoint {
+    config: ConstraintConfig,
+    world_anchor: Vec3,
+    world_axis: Vec3,
+    initialized: bool,
+    // the initial relative quaternion q1^-1 * q2
+    q0: Quat,
+
+    /// Lower and upper translation of body_b along the axis from its rest pose
+    pub limits: Option<(f32, f32)>,
+    pub motor: Option<PrismaticMotor>,
+    pub break_limits: Option<BreakLimits>,
+    /// Makes the joint springy, None holds it rigidly
+    pub soft: Option<SoftConstraint>,
+
+    jacobian: MatMN<ROWS, 12>,
+    cached_lambda: VecN<ROWS>,
+    lambda_min: VecN<ROWS>,
+    lambda_max: VecN<ROWS>,
+    baumgarte: VecN<ROWS>,
+    // the soft constraint's softness, as a multiple of each row's effective inverse mass
+    joint_softness: f32,
+    translation: f32,
+}
+
+impl PrismaticJoint {
+    pub fn new(body_a: Entity, body_b: Entity, anchor: Vec3, axis: Vec3) -> Self {
+        Self {
+            config: ConstraintConfig {
+                handle_a: body_a,
+                handle_b: body_b,
+                ..ConstraintConfig::default()
+            },
+            world_anchor: anchor,
+            world_axis: axis.normalize(),
+            initialized: false,
+            q0: Quat::IDENTITY,
+            limits: None,
+            motor: None,
+            break_limits: None,
+            soft: None,
+            joint_softness: 0.0,
+            jacobian: MatMN::zero(),
+            cached_lambda: VecN::zero(),
+            lambda_min: VecN::zero(),
+            lambda_max: VecN::zero(),
+            baumgarte: VecN::zero(),
+            translation: 0.0,
+        }
+    }
+
+    pub fn with_limits(mut self, lower: f32, upper: f32) -> Self {
+        self.limits = Some((lower, upper));
+        self
+    }
+
+    pub fn with_motor(mut self, target_speed: f32, max_force: f32) -> Self {
+        self.motor = Some(PrismaticMotor {
+            target_speed,
+            max_force,
+        });
+        self
+    }
+
+    /// Lets the joint give like a damped spring instead of holding rigidly
+    pub fn with_frequency(mut self, frequency: f32, damping_ratio: f32) -> Self {
+        self.soft = Some(SoftConstraint::new(frequency, damping_ratio));
+        self
+    }
+
+    /// Breaks the joint once it has to hold more than the force or torque
+    pub fn with_break_limits(mut self, force: f32, torque: f32) -> Self {
+        self.break_limits = Some(BreakLimits { force, torque });
+        self
+    }
+
+    /// Translation of body_b along the axis from its rest pose
+    pub fn translation(&self) -> f32 {
+        self.translation
+    }
+}
+
+impl Constraint for PrismaticJoint {
+    fn handles(&self) -> (Entity, Entity) {
+        (self.config.handle_a, self.config.handle_b)
+    }
+
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32) {
+        if !self.initialized {
+            let (config, q0) = ConstraintConfig::from_world(
+                bodies,
+                self.config.handle_a,
+                self.config.handle_b,
+                self.world_anchor,
+                self.world_axis,
+            );
+            self.config = config;
+            self.q0 = q0;
+            self.initialized = true;
+        }
+
+        let (body_a, trans_a, _) = bodies.get(self.config.handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(self.config.handle_b).unwrap();
+
+        // get the world space position of the anchor from body_a's orientation
+        let world_anchor_a = body_a.local_to_world(&trans_a, self.config.anchor_a);
+
+        // get the world space position of the anchor from body_b's orientation
+        let world_anchor_b = body_b.local_to_world(&trans_b, self.config.anchor_b);
+
+        let d = world_anchor_b - world_anchor_a;
+        let ra = world_anchor_a - body_a.centre_of_mass_world(&trans_a);
+        let rb = world_anchor_b - body_b.centre_of_mass_world(&trans_b);
+
+        // the axis is defined in the local space of body_a, and moves with it
+        let axis = trans_a.rotation * self.config.axis_a;
+        let (u, v) = axis.any_orthonormal_pair();
+        self.translation = d.dot(axis);
+
+        // get the orientation information of the bodies, q0 and -q0 are the same rotation so
+        // pick the one that keeps the relative quaternion's w positive
+        let q1 = trans_a.rotation;
+        let q2 = trans_b.rotation;
+        let q1_inv = q1.inverse();
+        let mut q0_inv = self.q0.inverse();
+        if (q1_inv * q2 * q0_inv).w < 0.0 {
+            q0_inv = -q0_inv;
+        }
+        let qrr = q1_inv * q2 * q0_inv;
+
+        let p = Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W);
+        let p_t = p.transpose(); // pointless but self documenting
+
+        let mat_a = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * -0.5;
+        let mat_b = p * quat_left(q1_inv) * quat_right(q2 * q0_inv) * p_t * 0.5;
+
+        self.jacobian = MatMN::zero();
+        self.lambda_min = VecN::zero();
+        self.lambda_max = VecN::zero();
+        self.baumgarte = VecN::zero();
+        const BETA: f32 = 0.05;
+        let (beta, softness) = soft_erp(self.soft, BETA, dt_sec);
+        self.joint_softness = softness;
+
+        // the separation of the anchors along a direction, body_a's side includes the lever arm
+        // to body_b's anchor since the direction turns with body_a
+        let linear_row = |dir: Vec3| (-dir, -(ra + d).cross(dir), dir, rb.cross(dir));
+
+        // the first two rows keep the anchors on the axis
+        for (row, dir) in [(0, u), (1, v)] {
+            let (j1, j2, j3, j4) = linear_row(dir);
+            set_jacobian_row(&mut self.jacobian, row, j1, j2, j3, j4);
+            self.baumgarte[row] = (beta / dt_sec) * d.dot(dir);
+        }
+
+        // the quaternion jacobians stop the bodies rotating relative to each other
+        for (row, dir) in [(2, Vec3::X), (3, Vec3::Y), (4, Vec3::Z)] {
+            set_jacobian_row(
+                &mut self.jacobian,
+                row,
+                Vec3::ZERO,
+                quat_jacobian(mat_a, dir),
+                Vec3::ZERO,
+                quat_jacobian(mat_b, dir),
+            );
+            self.baumgarte[row] = (beta / dt_sec) * qrr.xyz().dot(dir);
+        }
+        for row in 0..LIMIT_ROW {
+            self.lambda_min[row] = f32::NEG_INFINITY;
+            self.lambda_max[row] = f32::INFINITY;
+        }
+
+        // the limit only pushes back towards the allowed range
+        let violated = self
+            .limits
+            .and_then(|(lower, upper)| exceeded_limit(self.translation, lower, upper));
+        if let Some((limit, lambda_min, lambda_max)) = violated {
+            let (j1, j2, j3, j4) = linear_row(axis);
+            set_jacobian_row(&mut self.jacobian, LIMIT_ROW, j1, j2, j3, j4);
+            self.lambda_min[LIMIT_ROW] = lambda_min;
+            self.lambda_max[LIMIT_ROW] = lambda_max;
+            self.baumgarte[LIMIT_ROW] = (beta / dt_sec) * (self.translation - limit);
+        } else {
+            self.cached_lambda[LIMIT_ROW] = 0.0;
+        }
+
+        // the motor works on the relative velocity of the anchors along the axis
+        if let Some(motor) = self.motor {
+            let (j1, j2, j3, j4) = linear_row(axis);
+            set_jacobian_row(&mut self.jacobian, MOTOR_ROW, j1, j2, j3, j4);
+            let max_impulse = motor.max_force.abs() * dt_sec;
+            self.lambda_min[MOTOR_ROW] = -max_impulse;
+            self.lambda_max[MOTOR_ROW] = max_impulse;
+            self.baumgarte[MOTOR_ROW] = -motor.target_speed;
+        } else {
+            self.cached_lambda[MOTOR_ROW] = 0.0;
+        }
+
+        // apply warm starting from last frame
+        let impulses = self.jacobian.transpose() * self.cached_lambda;
+        self.config.apply_impulses(bodies, impulses);
+    }
+
+    fn solve(&mut self, bodies: &ConstraintBodies) {
+        let jacobian_transpose = self.jacobian.transpose();
+
+        // build the system of equations
+        let q_dt = self.config.get_velocities(bodies);
+        let inv_mass_matrix = self.config.get_inverse_mass_matrix(bodies);
+        let mut j_w_jt = MatN::from(self.jacobian * inv_mass_matrix * jacobian_transpose);
+        let mut rhs = self.jacobian * q_dt * -1.0;
+        for row in 0..ROWS {
+            rhs[row] -= self.baumgarte[row];
+        }
+
+        // a soft joint gives on every row but the motor's
+        soften_rows(
+            &mut j_w_jt,
+            &mut rhs,
+            &self.cached_lambda,
+            0..MOTOR_ROW,
+            self.joint_softness,
+        );
+
+        // solve for the Lagrange multipliers
+        let mut lambda_n = lcp_gauss_seidel(&j_w_jt, &rhs);
+
+        // accumulate the impulses and clamp the limit and motor rows to what they can apply
+        let old_lambda = self.cached_lambda;
+        self.cached_lambda += lambda_n;
+        for row in LIMIT_ROW..ROWS {
+            self.cached_lambda[row] =
+                self.cached_lambda[row].clamp(self.lambda_min[row], self.lambda_max[row]);
+        }
+        lambda_n = self.cached_lambda - old_lambda;
+
+        // apply the impulses
+        let impulses = jacobian_transpose * lambda_n;
+        self.config.apply_impulses(bodies, impulses);
+    }
+
+    fn post_solve(&mut self) {
+        // limit the warm starting to reasonable limits
+        for cached_lambda in self.cached_lambda.iter_mut() {
+            if !cached_lambda.is_finite() {
+                *cached_lambda = 0.0
+            }
+
+            const LIMIT: f32 = 1e5;
+            *cached_lambda = cached_lambda.clamp(-LIMIT, LIMIT);
+        }
+    }
+
+    fn break_limits(&self) -> Option<BreakLimits> {
+        self.break_limits
+    }
+
+    fn applied_impulse(&self) -> (f32, f32) {
+        applied_impulse(&self.jacobian, &self.cached_lambda)
+    }
+}
@@ -1,26 +1,29 @@
 #![allow(dead_code)]
-// mod constraint_constant_velocity;
-// mod constraint_distance;
-// mod constraint_hinge_quat;
-// mod constraint_motor;
-// mod constraint_mover;
-// mod constraint_orientation;
+pub mod constraint_constant_velocity;
+pub mod constraint_distance;
+pub mod constraint_hinge_quat;
+pub mod constraint_motor;
+pub mod constraint_mover;
+pub mod constraint_orientation;
 pub mod constraint_penetration;
+pub mod constraint_prismatic;
 
 use crate::{
-    
-    math::{MatMN, VecN},
+    phase::island::{constraint_edges_system, for_each_island, Islands},
     primitives::*,
-    PhysicsConfig,
+    run_physics, Physics, PhysicsConfig, PhysicsStage, PhysicsTime, Update,
 };
-use bevy::prelude::*;
-// use constraint_constant_velocity::ConstraintConstantVelocityLimited;
-// use constraint_distance::ConstraintDistance;
-// use constraint_hinge_quat::ConstraintHingeQuatLimited;
-// use constraint_motor::ConstraintMotor;
-// use constraint_mover::ConstraintMoverSimple;
-// use constraint_orientation::ConstraintOrientation;
+use bevy::{prelude::*, tasks::ComputeTaskPool};
+pub use constraint_constant_velocity::ConeTwistJoint;
+pub use constraint_distance::DistanceJoint;
+pub use constraint_hinge_quat::HingeJoint;
+pub use constraint_motor::{AngularMotor, MotorJoint, MotorTarget};
+pub use constraint_mover::TargetFollow;
+pub use constraint_orientation::FixedJoint;
 pub use constraint_penetration::ConstraintPenetration;
+pub use constraint_prismatic::{PrismaticJoint, PrismaticMotor};
+// for building constraints outside the crate
+pub use crate::math::{lcp_gauss_seidel, MatMN, MatN, VecN};
 
 pub fn quat_left(q: Quat) -> Mat4 {
     Mat4::from_cols(
@@ -40,27 +43,352 @@ pub fn quat_right(q: Quat) -> Mat4 {
     )
 }
 
+/// Writes one row of a jacobian, the linear and angular parts for body a then body b
+pub fn set_jacobian_row<const M: usize>(
+    jacobian: &mut MatMN<M, 12>,
+    row: usize,
+    j1: Vec3,
+    j2: Vec3,
+    j3: Vec3,
+    j4: Vec3,
+) {
+    for (i, j) in [j1, j2, j3, j4].into_iter().enumerate() {
+        jacobian.rows[row][i * 3] = j.x;
+        jacobian.rows[row][i * 3 + 1] = j.y;
+        jacobian.rows[row][i * 3 + 2] = j.z;
+    }
+}
+
+// The angular part of a quaternion jacobian row, mat is P * L(q1^-1) * R(q2 * q0^-1) * P^T scaled
+// by -0.5 for body a and 0.5 for body b
+fn quat_jacobian(mat: Mat4, axis: Vec3) -> Vec3 {
+    let tmp = mat * Vec4::from((0.0, axis));
+    Vec3::new(tmp[1], tmp[2], tmp[3])
+}
+
+// The limit an angle or translation has gone past, with the range the accumulated impulse is clamped to so it
+// only pushes back towards the allowed range
+fn exceeded_limit(value: f32, lower: f32, upper: f32) -> Option<(f32, f32, f32)> {
+    if value > upper {
+        Some((upper, f32::NEG_INFINITY, 0.0))
+    } else if value < lower {
+        Some((lower, 0.0, f32::INFINITY))
+    } else {
+        None
+    }
+}
+
+// Turns a spring's stiffness and damping into the softness added to a row's effective mass and
+// the fraction of the error corrected each substep, so the row acts as an implicit spring
+fn soft_coefficients(stiffness: f32, damping: f32, dt_sec: f32) -> (f32, f32) {
+    let d = damping + dt_sec * stiffness;
+    if d <= 0.0 {
+        return (0.0, 0.0);
+    }
+    (1.0 / (dt_sec * d), dt_sec * stiffness / d)
+}
+
+// The size of the linear and angular impulse the rows put on body_a, rows without a linear part
+// only twist the bodies so they count towards the angular impulse
+fn applied_impulse<const M: usize>(jacobian: &MatMN<M, 12>, lambda: &VecN<M>) -> (f32, f32) {
+    let mut linear = Vec3::ZERO;
+    let mut angular = Vec3::ZERO;
+    for (row, lambda) in jacobian.rows.iter().zip(lambda.iter()) {
+        let j1 = Vec3::from_slice(&row[0..3]);
+        if j1 == Vec3::ZERO {
+            angular += Vec3::from_slice(&row[3..6]) * *lambda;
+        } else {
+            linear += j1 * *lambda;
+        }
+    }
+    (linear.length(), angular.length())
+}
+
+/// Force and torque a joint can hold, it's removed once either is exceeded. Use `f32::INFINITY` for
+/// one that should never break.
+#[derive(Copy, Clone, Debug)]
+pub struct BreakLimits {
+    pub force: f32,
+    pub torque: f32,
+}
+
+/// Sent when a joint breaks under load. A joint on its own entity has been despawned, one sharing an
+/// entity with a body has only lost its component
+#[derive(Debug)]
+pub struct JointBroken {
+    /// Entity the joint was on
+    pub joint: Entity,
+    pub a: Entity,
+    pub b: Entity,
+    /// The impulse over the substep that went past the limit, linear or angular
+    pub impulse: f32,
+}
+
+/// Makes a joint act like a damped spring pulling it back to its rest pose, instead of holding it
+/// rigidly. The spring is scaled by the mass it moves, so it behaves the same on light and heavy
+/// bodies.
+#[derive(Copy, Clone, Debug)]
+pub struct SoftConstraint {
+    /// How many times a second the joint would oscillate without damping, in Hz
+    pub frequency: f32,
+    /// 0.0 keeps oscillating, 1.0 settles as fast as it can without overshooting
+    pub damping_ratio: f32,
+}
+
+impl SoftConstraint {
+    pub fn new(frequency: f32, damping_ratio: f32) -> Self {
+        Self {
+            frequency,
+            damping_ratio,
+        }
+    }
+}
+
+// The fraction of the error a joint corrects each substep and its softness as a multiple of each
+// row's effective inverse mass, rigid joints keep their own baumgarte factor. Per unit of mass the
+// spring has a stiffness of w^2 and a damping of 2 * zeta * w, where w = 2 * pi * frequency
+fn soft_erp(soft: Option<SoftConstraint>, beta: f32, dt_sec: f32) -> (f32, f32) {
+    match soft {
+        Some(soft) => {
+            let omega = std::f32::consts::TAU * soft.frequency;
+            let stiffness = omega * omega;
+            let damping = 2.0 * soft.damping_ratio * omega;
+            let (softness, erp) = soft_coefficients(stiffness, damping, dt_sec);
+            (erp, softness)
+        }
+        None => (beta, 0.0),
+    }
+}
+
+// Adds a soft joint's softness to the rows, scaled by each row's effective inverse mass. The more
+// impulse a row is already holding the more it gives
+fn soften_rows<const M: usize>(
+    j_w_jt: &mut MatN<M>,
+    rhs: &mut VecN<M>,
+    cached_lambda: &VecN<M>,
+    rows: std::ops::Range<usize>,
+    softness: f32,
+) {
+    for row in rows {
+        let softness = softness * j_w_jt.rows[row][row];
+        j_w_jt.rows[row][row] += softness;
+        rhs[row] -= softness * cached_lambda[row];
+    }
+}
+
+/// The bodies a constraint reads and pushes, locked axes are taken into account by the
+/// [`ConstraintConfig`] helpers
+pub type ConstraintBodies<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Body,
+        &'static mut GlobalTransform,
+        Option<&'static LockedAxes>,
+    ),
+>;
+
 pub trait Constraint: Send + Sync {
-    fn pre_solve(&mut self, query: &mut Query<(Entity, &mut Body, &mut Transform)>, dt_sec: f32);
-    fn solve(&mut self, bodies: &mut Query<(Entity, &mut Body, &mut Transform)>);
+    /// The two bodies the constraint acts on, used to put it in their island
+    fn handles(&self) -> (Entity, Entity);
+    fn pre_solve(&mut self, bodies: &ConstraintBodies, dt_sec: f32);
+    fn solve(&mut self, bodies: &ConstraintBodies);
     fn post_solve(&mut self) {}
+    /// Force and torque the constraint can take before it breaks, None never breaks
+    fn break_limits(&self) -> Option<BreakLimits> {
+        None
+    }
+    /// Size of the linear and angular impulse the constraint applied over the last substep
+    fn applied_impulse(&self) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+    /// Whether gameplay has changed the constraint since its bodies were last woken, like a new
+    /// motor target. A constraint between two sleeping bodies isn't solved, so the change would
+    /// otherwise never be seen
+    fn needs_wake(&self) -> bool {
+        false
+    }
+    /// Called once the bodies have been woken for the change
+    fn set_woken(&mut self) {}
+}
+
+// Removes constraints whose bodies are gone, and sorts the rest by island. A constraint on its own
+// entity is despawned with it, one sharing an entity with a body only loses its component
+fn island_groups<T: Constraint + Component>(
+    commands: &mut Commands,
+    islands: &Islands,
+    query: &Query<(Entity, &mut T)>,
+    bodies: &ConstraintBodies,
+    system: &str,
+) -> Vec<Vec<Entity>> {
+    let mut valid = Vec::new();
+    for (e, constraint) in query.iter() {
+        let (a, b) = constraint.handles();
+        if bodies.get(a).is_err() || bodies.get(b).is_err() {
+            warn!("{} System Remove: {:?}", system, e);
+            if bodies.get(e).is_ok() {
+                commands.entity(e).remove::<T>();
+            } else {
+                commands.entity(e).despawn();
+            }
+            continue;
+        }
+        valid.push((e, (a, b)));
+    }
+
+    islands
+        .group(valid, |(_, handles)| *handles)
+        .into_iter()
+        .map(|group| group.into_iter().map(|(e, _)| e).collect())
+        .collect()
+}
+
+pub fn pre_solve_system<T: Constraint + Component>(
+    mut commands: Commands,
+    pt: Res<PhysicsTime>,
+    config: Res<PhysicsConfig>,
+    pool: Res<ComputeTaskPool>,
+    islands: Res<Islands>,
+    query: Query<(Entity, &mut T)>,
+    bodies: ConstraintBodies,
+) {
+    let groups = island_groups(&mut commands, &islands, &query, &bodies, "Pre Solve");
+
+    let (query, bodies) = (&query, &bodies);
+    let dt = pt.time;
+    for_each_island(&pool, config.parallel, groups, |group| {
+        for e in group {
+            // Safety: each island is only touched by one task
+            let (_, mut constraint) = unsafe { query.get_unchecked(e).unwrap() };
+            constraint.pre_solve(bodies, dt);
+        }
+    });
 }
 
 pub fn solve_system<T: Constraint + Component>(
+    mut commands: Commands,
     config: Res<PhysicsConfig>,
-    mut query: Query<&mut T>,
-    mut bodies: Query<(Entity, &mut Body, &mut Transform)>,
+    pool: Res<ComputeTaskPool>,
+    islands: Res<Islands>,
+    query: Query<(Entity, &mut T)>,
+    bodies: ConstraintBodies,
 ) {
-    for mut constraint in query.iter_mut() {
-        for _ in 0..config.constrain_max_iter {
-            constraint.solve(&mut bodies);
+    let groups = island_groups(&mut commands, &islands, &query, &bodies, "Solve");
+
+    let (query, bodies) = (&query, &bodies);
+    let iterations = config.constrain_max_iter;
+    for_each_island(&pool, config.parallel, groups, |group| {
+        for _ in 0..iterations {
+            for e in group.iter() {
+                // Safety: each island is only touched by one task
+                let (_, mut constraint) = unsafe { query.get_unchecked(*e).unwrap() };
+                constraint.solve(bodies);
+            }
         }
+    });
+}
+
+pub fn post_solve_system<T: Constraint + Component>(
+    mut commands: Commands,
+    pt: Res<PhysicsTime>,
+    islands: Res<Islands>,
+    mut broken: EventWriter<JointBroken>,
+    mut query: Query<(Entity, &mut T)>,
+    bodies: ConstraintBodies,
+) {
+    for (e, mut constraint) in query.iter_mut() {
+        // only constraints in an island were solved this substep, the rest hold the impulse from
+        // whenever they last ran
+        let (a, b) = constraint.handles();
+        if islands
+            .island_of(a)
+            .or_else(|| islands.island_of(b))
+            .is_none()
+        {
+            continue;
+        }
+        constraint.post_solve();
+
+        // the limits are forces, so compare them with the impulses over the substep
+        let limits = match constraint.break_limits() {
+            Some(limits) => limits,
+            None => continue,
+        };
+        let (linear, angular) = constraint.applied_impulse();
+        let impulse = if linear > limits.force * pt.time {
+            linear
+        } else if angular > limits.torque * pt.time {
+            angular
+        } else {
+            continue;
+        };
+
+        if bodies.get(e).is_ok() {
+            commands.entity(e).remove::<T>();
+        } else {
+            commands.entity(e).despawn();
+        }
+        broken.send(JointBroken {
+            joint: e,
+            a,
+            b,
+            impulse,
+        });
     }
 }
 
-pub fn post_solve_system<T: Constraint + Component>(mut query: Query<&mut T>) {
+/// Wakes both bodies of constraints that gameplay has changed, before the step sees them
+pub fn wake_system<T: Constraint + Component>(mut commands: Commands, mut query: Query<&mut T>) {
     for mut constraint in query.iter_mut() {
-        constraint.post_solve();
+        // only borrow mutably when there's a change, so the constraint isn't flagged every frame
+        if !constraint.needs_wake() {
+            continue;
+        }
+        constraint.set_woken();
+        let (a, b) = constraint.handles();
+        commands.entity(a).remove::<Sleeping>();
+        commands.entity(b).remove::<Sleeping>();
+    }
+}
+
+/// Registers a constraint type with the physics schedule, so joints written outside the crate run
+/// the same way as the built in ones. Call it after adding the [`crate::PhysicsPlugin`].
+pub trait AddConstraint {
+    /// Adds `T` to the islands and runs its `pre_solve`, `solve` and `post_solve` every substep
+    fn add_constraint<T: Constraint + Component>(&mut self) -> &mut Self;
+}
+
+impl AddConstraint for App {
+    fn add_constraint<T: Constraint + Component>(&mut self) -> &mut Self {
+        self.add_system_set_to_stage(
+            PhysicsStage,
+            SystemSet::new()
+                .label(Physics::Update)
+                .after(Physics::PreUpdate)
+                .with_run_criteria(run_physics)
+                .with_system(
+                    constraint_edges_system::<T>
+                        .before(Update::Islands)
+                        .after(Update::Dynamics),
+                )
+                .with_system(
+                    pre_solve_system::<T>
+                        .label(Update::ConstraintsPreSolve)
+                        .after(Update::ResolveContact),
+                )
+                .with_system(
+                    solve_system::<T>
+                        .label(Update::ConstraintsSolve)
+                        .after(Update::ConstraintsPreSolve),
+                )
+                .with_system(
+                    post_solve_system::<T>
+                        .label(Update::ConstraintsPostSolve)
+                        .after(Update::ConstraintsSolve),
+                ),
+        )
+        // gameplay changes constraints in update, so wake their bodies before the step
+        .add_system_to_stage(CoreStage::PostUpdate, wake_system::<T>)
     }
 }
 
@@ -276,20 +604,45 @@ impl Default for ConstraintConfig {
 }
 
 impl ConstraintConfig {
-    fn get_inverse_mass_matrix(
-        &self,
-        bodies: &mut Query<(&mut Body, &mut GlobalTransform)>,
-    ) -> MatMN<12, 12> {
+    /// Joints are given a world space anchor and axis, these are stored in each body's space from
+    /// their current poses, along with the relative orientation q1^-1 * q2
+    pub fn from_world(
+        bodies: &ConstraintBodies,
+        handle_a: Entity,
+        handle_b: Entity,
+        world_anchor: Vec3,
+        world_axis: Vec3,
+    ) -> (Self, Quat) {
+        let (body_a, trans_a, _) = bodies.get(handle_a).unwrap();
+        let (body_b, trans_b, _) = bodies.get(handle_b).unwrap();
+
+        let config = Self {
+            handle_a,
+            handle_b,
+            anchor_a: body_a.world_to_local(&trans_a, world_anchor),
+            axis_a: trans_a.rotation.inverse() * world_axis,
+            anchor_b: body_b.world_to_local(&trans_b, world_anchor),
+            axis_b: trans_b.rotation.inverse() * world_axis,
+        };
+        (config, trans_a.rotation.inverse() * trans_b.rotation)
+    }
+
+    /// The inverse mass and world space inverse inertia of both bodies, with their locked axes
+    /// zeroed
+    pub fn get_inverse_mass_matrix(&self, bodies: &ConstraintBodies) -> MatMN<12, 12> {
         let mut inv_mass_matrix = MatMN::zero();
 
         {
-            let (body_a, trans_a) = bodies.get_mut(self.handle_a).unwrap();
+            let (body_a, trans_a, locks_a) = bodies.get(self.handle_a).unwrap();
+            let locks_a = locks_a.copied().unwrap_or_default();
 
-            inv_mass_matrix.rows[0][0] = body_a.inv_mass;
-            inv_mass_matrix.rows[1][1] = body_a.inv_mass;
-            inv_mass_matrix.rows[2][2] = body_a.inv_mass;
+            // locked translation axes get no linear response
+            let inv_mass_a = body_a.effective_inv_mass() * locks_a.translation_mask();
+            inv_mass_matrix.rows[0][0] = inv_mass_a.x;
+            inv_mass_matrix.rows[1][1] = inv_mass_a.y;
+            inv_mass_matrix.rows[2][2] = inv_mass_a.z;
 
-            let inv_intertia_a = body_a.inv_inertia_tensor_world(&trans_a);
+            let inv_intertia_a = body_a.inv_inertia_tensor_world(&trans_a, locks_a);
             for i in 0..3 {
                 inv_mass_matrix.rows[3 + i][3] = inv_intertia_a.col(i)[0];
                 inv_mass_matrix.rows[3 + i][3 + 1] = inv_intertia_a.col(i)[1];
@@ -298,12 +651,14 @@ impl ConstraintConfig {
         }
 
         {
-            let (body_b, trans_b) = bodies.get_mut(self.handle_b).unwrap();
-            inv_mass_matrix.rows[6][6] = body_b.inv_mass;
-            inv_mass_matrix.rows[7][7] = body_b.inv_mass;
-            inv_mass_matrix.rows[8][8] = body_b.inv_mass;
-
-            let inv_intertia_b = body_b.inv_inertia_tensor_world(&trans_b);
+            let (body_b, trans_b, locks_b) = bodies.get(self.handle_b).unwrap();
+            let locks_b = locks_b.copied().unwrap_or_default();
+            let inv_mass_b = body_b.effective_inv_mass() * locks_b.translation_mask();
+            inv_mass_matrix.rows[6][6] = inv_mass_b.x;
+            inv_mass_matrix.rows[7][7] = inv_mass_b.y;
+            inv_mass_matrix.rows[8][8] = inv_mass_b.z;
+
+            let inv_intertia_b = body_b.inv_inertia_tensor_world(&trans_b, locks_b);
             for i in 0..3 {
                 inv_mass_matrix.rows[9 + i][9] = inv_intertia_b.col(i)[0];
                 inv_mass_matrix.rows[9 + i][9 + 1] = inv_intertia_b.col(i)[1];
@@ -314,11 +669,12 @@ impl ConstraintConfig {
         inv_mass_matrix
     }
 
-    fn get_velocities(&self, bodies: &mut Query<(&mut Body, &mut GlobalTransform)>) -> VecN<12> {
+    /// The linear and angular velocities of body a then body b
+    pub fn get_velocities(&self, bodies: &ConstraintBodies) -> VecN<12> {
         let mut q_dt = VecN::zero();
 
         {
-            let (body_a, _trans_a) = bodies.get_mut(self.handle_a).unwrap();
+            let (body_a, _trans_a, _) = bodies.get(self.handle_a).unwrap();
 
             q_dt[0] = body_a.linear_velocity.x;
             q_dt[1] = body_a.linear_velocity.y;
@@ -330,7 +686,7 @@ impl ConstraintConfig {
         }
 
         {
-            let (body_b, _trans_b) = bodies.get_mut(self.handle_b).unwrap();
+            let (body_b, _trans_b, _) = bodies.get(self.handle_b).unwrap();
 
             q_dt[6] = body_b.linear_velocity.x;
             q_dt[7] = body_b.linear_velocity.y;
@@ -344,21 +700,232 @@ impl ConstraintConfig {
         q_dt
     }
 
-    fn apply_impulses(&self, bodies: &mut Query<(&mut Body, &mut GlobalTransform)>, impulses: VecN<12>) {
-        {
-            let force_internal_a = Vec3::from_slice(&impulses[0..]);
-            let torque_internal_a = Vec3::from_slice(&impulses[3..]);
-            let (mut body_a, trans_a) = bodies.get_mut(self.handle_a).unwrap();
-            body_a.apply_impulse_linear(force_internal_a);
-            body_a.apply_impulse_angular(torque_internal_a, &trans_a);
-        }
+    /// Applies the linear and angular impulses of body a then body b, bodies with infinite mass
+    /// are left alone. This is the only way a constraint should change the bodies.
+    //
+    // Islands are solved in parallel, each island has its own bodies so this only needs shared
+    // access to the query. Bodies with infinite mass are shared between islands, they are only ever
+    // read so they are never borrowed mutably.
+    pub fn apply_impulses(&self, bodies: &ConstraintBodies, impulses: VecN<12>) {
+        for (e, offset) in [(self.handle_a, 0), (self.handle_b, 6)] {
+            let (body, _, locks) = bodies.get(e).unwrap();
+            if body.has_infinite_mass() {
+                continue;
+            }
 
-        {
-            let force_internal_b = Vec3::from_slice(&impulses[6..]);
-            let torque_internal_b = Vec3::from_slice(&impulses[9..]);
-            let (mut body_b, trans_b) = bodies.get_mut(self.handle_b).unwrap();
-            body_b.apply_impulse_linear(force_internal_b);
-            body_b.apply_impulse_angular(torque_internal_b, &trans_b);
+            // Safety: a body with finite mass is only in the island of the constraints acting on it
+            let locks = locks.copied().unwrap_or_default();
+            let (mut body, trans, _) = unsafe { bodies.get_unchecked(e).unwrap() };
+            let force_internal = Vec3::from_slice(&impulses[offset..]);
+            let torque_internal = Vec3::from_slice(&impulses[offset + 3..]);
+            body.apply_impulse_linear(force_internal, locks);
+            body.apply_impulse_angular(torque_internal, &trans, locks);
         }
     }
 }
+
+#[cfg(test)]
+fn test_constraint_world() -> World {
+    use crate::phase::island::ConstraintEdges;
+    use bevy::{app::Events, tasks::TaskPool};
+
+    let mut world = World::default();
+    world.insert_resource(PhysicsConfig::default());
+    world.insert_resource(PhysicsTime {
+        time: 0.1,
+        step_time: 0.1,
+        ..Default::default()
+    });
+    world.insert_resource(ComputeTaskPool(TaskPool::new()));
+    world.insert_resource(Islands::default());
+    world.insert_resource(ConstraintEdges::default());
+    world.insert_resource(Events::<Contact>::default());
+    world.insert_resource(Events::<ManifoldContactEvent>::default());
+    world.insert_resource(Events::<JointBroken>::default());
+    world
+}
+
+#[cfg(test)]
+fn test_anchor(world: &mut World) -> Entity {
+    world
+        .spawn()
+        .insert_bundle((
+            Body {
+                body_type: RigidBody::Static,
+                ..Default::default()
+            },
+            GlobalTransform::identity(),
+        ))
+        .id()
+}
+
+#[cfg(test)]
+fn test_body(world: &mut World, position: Vec3, velocity: Vec3) -> Entity {
+    world
+        .spawn()
+        .insert_bundle((
+            Body {
+                linear_velocity: velocity,
+                ..Default::default()
+            },
+            GlobalTransform::from_translation(position),
+        ))
+        .id()
+}
+
+// One substep of the constraint and nothing else, the bodies don't move
+#[cfg(test)]
+fn test_constraint_step<T: Constraint + Component>(world: &mut World) {
+    use crate::phase::island::build_islands_system;
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(constraint_edges_system::<T>.before(Update::Islands))
+        .add_system(build_islands_system.label(Update::Islands))
+        .add_system(
+            pre_solve_system::<T>
+                .label(Update::ConstraintsPreSolve)
+                .after(Update::Islands),
+        )
+        .add_system(
+            solve_system::<T>
+                .label(Update::ConstraintsSolve)
+                .after(Update::ConstraintsPreSolve),
+        )
+        .add_system(post_solve_system::<T>.after(Update::ConstraintsSolve));
+    stage.run(world);
+}
+
+#[test]
+fn test_joint_breaks_above_limit() {
+    use bevy::app::Events;
+
+    let mut world = test_constraint_world();
+
+    // a body leaving a static anchor at 10m/s on a 1m rod, stopping it takes an impulse of 10
+    let anchor = test_anchor(&mut world);
+    let body = test_body(&mut world, Vec3::X, Vec3::X * 10.0);
+    let joint = world
+        .spawn()
+        .insert(DistanceJoint::new(
+            anchor,
+            Vec3::ZERO,
+            body,
+            Vec3::ZERO,
+            1.0,
+        ))
+        .id();
+
+    // without limits the rod holds
+    test_constraint_step::<DistanceJoint>(&mut world);
+    assert!(world.get::<DistanceJoint>(joint).is_some());
+
+    // asleep it isn't solved, the impulse it held last step doesn't count
+    world.get_mut::<DistanceJoint>(joint).unwrap().break_limits = Some(BreakLimits {
+        force: 1.0,
+        torque: f32::INFINITY,
+    });
+    world.entity_mut(body).insert(Sleeping {
+        translation: Vec3::X,
+        rotation: Quat::IDENTITY,
+    });
+    test_constraint_step::<DistanceJoint>(&mut world);
+    assert!(world.get::<DistanceJoint>(joint).is_some());
+
+    // awake and pulling away again it needs far more than the 0.1 the limit allows this substep
+    world.entity_mut(body).remove::<Sleeping>();
+    world.get_mut::<Body>(body).unwrap().linear_velocity = Vec3::X * 10.0;
+    test_constraint_step::<DistanceJoint>(&mut world);
+    assert!(world.get_entity(joint).is_none());
+
+    let events = world.get_resource::<Events<JointBroken>>().unwrap();
+    let broken = events.get_reader().iter(events).collect::<Vec<_>>();
+    assert_eq!(broken.len(), 1);
+    assert_eq!(
+        (broken[0].joint, broken[0].a, broken[0].b),
+        (joint, anchor, body)
+    );
+    assert!(broken[0].impulse > 0.1);
+
+    // a joint on the body's own entity only loses its component, the body stays
+    let body = test_body(&mut world, Vec3::X, Vec3::X * 10.0);
+    world.entity_mut(body).insert(DistanceJoint::new(
+        anchor,
+        Vec3::ZERO,
+        body,
+        Vec3::ZERO,
+        1.0,
+    ));
+    world.get_mut::<DistanceJoint>(body).unwrap().break_limits = Some(BreakLimits {
+        force: 1.0,
+        torque: f32::INFINITY,
+    });
+    test_constraint_step::<DistanceJoint>(&mut world);
+    assert!(world.get::<DistanceJoint>(body).is_none());
+    assert!(world.get::<Body>(body).is_some());
+}
+
+#[test]
+fn test_rope_slack_inside_limits() {
+    let mut world = test_constraint_world();
+    let anchor = test_anchor(&mut world);
+
+    // a rope up to 2m long, the body is free inside it
+    let rope =
+        |body| DistanceJoint::new(anchor, Vec3::ZERO, body, Vec3::ZERO, 2.0).with_limits(0.0, 2.0);
+    let slack = test_body(&mut world, Vec3::X, Vec3::X * 5.0);
+    world.spawn().insert(rope(slack));
+
+    // just past its length the rope pulls the body back, but never pushes it out
+    let leaving = test_body(&mut world, Vec3::X * 2.05, Vec3::X * 5.0);
+    world.spawn().insert(rope(leaving));
+    let returning = test_body(&mut world, Vec3::X * 2.05, Vec3::X * -5.0);
+    world.spawn().insert(rope(returning));
+
+    test_constraint_step::<DistanceJoint>(&mut world);
+
+    let velocity = |e| world.get::<Body>(e).unwrap().linear_velocity;
+    assert_eq!(velocity(slack), Vec3::X * 5.0);
+    // stopped, with a little extra to take it back inside
+    assert!(velocity(leaving).x <= 0.0 && velocity(leaving).x > -0.5);
+    assert_eq!(velocity(returning), Vec3::X * -5.0);
+}
+
+#[test]
+fn test_soft_joint_oscillates_at_its_frequency() {
+    // a body stretched half a metre past a 1m soft rod, let go from rest
+    let simulate = |damping_ratio: f32| {
+        let mut world = test_constraint_world();
+        world.get_resource_mut::<PhysicsTime>().unwrap().time = 0.01;
+        let anchor = test_anchor(&mut world);
+        let body = test_body(&mut world, Vec3::X * 1.5, Vec3::ZERO);
+        world.spawn().insert(
+            DistanceJoint::new(anchor, Vec3::ZERO, body, Vec3::ZERO, 1.0)
+                .with_frequency(1.0, damping_ratio),
+        );
+
+        // move the body between the substeps, two seconds of its position and velocity
+        (0..200)
+            .map(|_| {
+                test_constraint_step::<DistanceJoint>(&mut world);
+                let velocity = world.get::<Body>(body).unwrap().linear_velocity;
+                let mut transform = world.get_mut::<GlobalTransform>(body).unwrap();
+                transform.translation += velocity * 0.01;
+                (transform.translation.x, velocity.x)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // undamped it swings through to the other side and turns back after half a period
+    let steps = simulate(0.0);
+    let turn = steps.windows(2).position(|w| w[0].1 < 0.0 && w[1].1 >= 0.0);
+    let half_period = (turn.unwrap() + 2) as f32 * 0.01;
+    assert!(half_period > 0.45 && half_period < 0.55);
+    let furthest = steps.iter().map(|(x, _)| *x).fold(f32::INFINITY, f32::min);
+    assert!(furthest > 0.5 && furthest < 0.6);
+
+    // critically damped it settles onto the rest length without overshooting
+    let steps = simulate(1.0);
+    assert!(steps.iter().all(|(x, _)| *x > 0.999));
+    assert!((steps[99].0 - 1.0).abs() < 0.02);
+}
@@ -1,6 +1,6 @@
 mod report;
 
-use crate::{Physics, PhysicsConfig, DebugMode, bounds::{aabb::Aabb, debug::{DebugBounds, DebugBoundsMesh}}, colliders::ColliderBox};
+use crate::{Physics, PhysicsConfig, DebugMode, bounds::{BoundingSystem, aabb::Aabb, debug::{DebugBounds, DebugBoundsMesh, update_debug_meshes, update_debug_mesh_visibility}}, colliders::ColliderBox};
 use bevy::{prelude::*, ecs::schedule::ShouldRun};
 use bevy_polyline::*;
 pub use report::*;
@@ -31,7 +31,19 @@ impl Plugin for PhysicsDebugPlugin {
                         .label(Physics::PostUpdate)
                         .with_run_criteria(run_debug_off)
                         .with_system(remove_debug_system)
-                    );
+                    )
+                // physics computes the aabbs from the colliders, we only need the debug meshes,
+                // these run after the physics stage so the meshes match this frame
+                .add_system_to_stage(
+                    CoreStage::Last,
+                    update_debug_meshes::<Aabb>.label(BoundingSystem::UpdateDebug),
+                )
+                // the debug meshes only exist once update_debug_meshes has run, so their visibility
+                // has to follow it, the next frame's visibility check picks it up
+                .add_system_to_stage(
+                    CoreStage::Last,
+                    update_debug_mesh_visibility::<Aabb>.after(BoundingSystem::UpdateDebug),
+                );
     }
 }
 
@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
 
-use crate::{constraints::ConstraintPenetration, primitives::*, PhysicsTime};
+use crate::{constraints::ConstraintPenetration, phase::broad::BroadphasePairs, primitives::*, PhysicsTime};
 
 #[derive(Inspectable, Default, Debug, Copy, Clone)]
 // TODO: Make this disable so user knows they can't change anything
@@ -18,7 +18,7 @@ pub struct PhysicsReport {
 pub fn report_system(
     pt: Res<PhysicsTime>,
     bodies: Query<(&Body, &Transform)>,
-    mut collision_pairs: EventReader<BroadContact>,
+    collision_pairs: Res<BroadphasePairs>,
     mut contacts: EventReader<Contact>,
     manifolds: Query<&Manifold>,
     constraint_penetrations: Query<&ConstraintPenetration>,
@@ -27,7 +27,7 @@ pub fn report_system(
     report.time = pt.time;
     report.bodies = bodies.iter().count();
     report.manifolds = manifolds.iter().count();
-    report.broad_contacts = collision_pairs.iter().count();
+    report.broad_contacts = collision_pairs.pairs.len();
     report.narrow_contacts = contacts.iter().count();
     report.constraint = constraint_penetrations.iter().count();
 }
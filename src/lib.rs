@@ -15,17 +15,38 @@ pub mod primitives;
 
 use bounds::{aabb::Aabb, *};
 use colliders::{Collider, ColliderBox, ColliderSphere};
+use constraints::{
+    AddConstraint, ConeTwistJoint, ConstraintPenetration, DistanceJoint, FixedJoint, HingeJoint,
+    MotorJoint, PrismaticJoint, TargetFollow,
+};
 use primitives::*;
 
-use bevy::{ecs::schedule::ShouldRun, prelude::*, transform::TransformSystem};
+use bevy::{ecs::schedule::ShouldRun, prelude::*, transform::TransformSystem, utils::Instant};
 use bevy_inspector_egui::Inspectable;
 use phase::*;
 
 /// Continuous Collision etection
 #[derive(Inspectable, PartialEq, Eq)]
 pub enum CollisionDetection {
+    /// Overlap tests at the start of the step, only pairs with a [`primitives::Ccd`] body are swept
     Static,
+    /// Every pair is swept for its time of impact
     Dynamic,
+    /// Like Static, but separated pairs that would touch this step get a speculative contact that
+    /// only removes the closing speed needed to stop them overlapping
+    Speculative,
+}
+
+/// How ballistic contacts are resolved each substep
+#[derive(Inspectable, PartialEq, Eq)]
+pub enum ContactResolution {
+    /// Every contact at once at the start of the substep, with islands solved in parallel. Swept
+    /// impacts later in the substep only lose the speed that would carry them through, they bounce
+    /// on the next substep
+    Islands,
+    /// One impact at a time in time of impact order, moving every body up to each impact. Runs
+    /// serially, but a body can be hit several times in one substep
+    TimeOfImpact,
 }
 
 #[derive(Inspectable, PartialEq, Eq)]
@@ -33,35 +54,112 @@ pub enum DebugMode {
     Off,
     Bounds,
 }
+
+/// How the time for each physics step is picked
+#[derive(Inspectable, PartialEq, Eq)]
+pub enum Timestep {
+    /// One step per frame using the frame delta, clamped to 0.1 seconds
+    Variable,
+    /// Steps of `1 / fixed_rate` seconds, run as many times as needed to catch up with the frame
+    Fixed,
+}
+
 #[derive(Component, Inspectable)]
 pub struct PhysicsConfig {
     pub enabled: bool,
     pub collision_dection: CollisionDetection,
+    pub contact_resolution: ContactResolution,
+    pub timestep: Timestep,
+    /// Steps per second when using a fixed timestep
+    #[inspectable(min = 1.0, max = 240.0)]
+    pub fixed_rate: f32,
+    /// Most fixed steps run in one frame, any time past that is dropped so we can't spiral
+    pub max_steps: usize,
+    /// Each step is split into this many substeps of dynamics, contacts and constraints
+    #[inspectable(min = 1, max = 16)]
+    pub substeps: usize,
     #[inspectable(min = -10.0, max = 10.0)]
     pub time_dilation: f32,
     pub gravity: Vec3,
     pub constrain_max_iter: usize,
+    /// Let islands at rest fall asleep, sleeping bodies are skipped until something wakes them
+    pub sleep_enabled: bool,
+    /// Bodies slower than this in m/s count as resting
+    pub sleep_linear_threshold: f32,
+    /// Bodies spinning slower than this in rad/s count as resting
+    pub sleep_angular_threshold: f32,
+    /// Seconds every body in an island has to be resting before the island falls asleep
+    pub sleep_time: f32,
+    /// Solve islands in parallel on the ComputeTaskPool, gives the same results as the serial path
+    pub parallel: bool,
     pub debug_mode: DebugMode,
 }
 
 #[derive(Default)]
 pub struct PhysicsTime {
     time: f32,
+    step_time: f32,
+    substep: usize,
+    accumulator: f32,
+    alpha: f32,
+    steps: usize,
+    last_update: Option<Instant>,
+}
+
+impl PhysicsTime {
+    /// Length of the current substep in seconds, this is the time bodies are integrated over
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Length of the whole physics step in seconds, split evenly between the substeps
+    pub fn step_time(&self) -> f32 {
+        self.step_time
+    }
+
+    /// Index of the substep currently running
+    pub fn substep(&self) -> usize {
+        self.substep
+    }
+
+    /// How far rendering is between the last two physics steps, 0 is the previous and 1 the current
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// Physics steps run this frame
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
 }
 
 impl Default for PhysicsConfig {
     fn default() -> Self {
         Self {
             enabled: true,
+            timestep: Timestep::Variable,
+            fixed_rate: 60.0,
+            max_steps: 5,
+            substeps: 1,
             gravity: Vec3::new(0.0, -9.8, 0.0),
             constrain_max_iter: 5,
+            sleep_enabled: true,
+            sleep_linear_threshold: 0.1,
+            sleep_angular_threshold: 0.1,
+            sleep_time: 0.5,
+            parallel: true,
             time_dilation: 1.0,
             collision_dection: CollisionDetection::Static,
+            contact_resolution: ContactResolution::Islands,
             debug_mode: DebugMode::Bounds,
         }
     }
 }
 
+/// Physics runs in its own stage after [`CoreStage::PostUpdate`], so it can be stepped 0..N times a frame
+#[derive(Clone, Hash, Debug, PartialEq, Eq, StageLabel)]
+pub struct PhysicsStage;
+
 #[derive(Clone, Hash, Debug, PartialEq, Eq, SystemLabel)]
 pub enum Physics {
     First,
@@ -83,11 +181,15 @@ pub enum Update {
     Dynamics,
     Broadphase,
     Narrowphase,
+    Islands,
     Manifold,
     ConstraintsPreSolve,
     ConstraintsSolve,
+    ConstraintsPostSolve,
     ResolveContact,
     Transform,
+    Sleep,
+    Interpolate,
 }
 
 pub struct StepOnceEvent;
@@ -97,65 +199,87 @@ impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<PhysicsConfig>()
             .init_resource::<PhysicsTime>()
+            .init_resource::<broad::StaticBroadphase>()
+            .init_resource::<broad::BroadphasePairs>()
+            .init_resource::<island::Islands>()
+            .init_resource::<island::ConstraintEdges>()
             .add_event::<Contact>()
-            .add_event::<BroadContact>()
             .add_event::<ManifoldContactEvent>()
             .add_event::<StepOnceEvent>()
-            // TODO: right now this uses the mesh instead of the collider
-            .add_plugin(BoundingVolumePlugin::<aabb::Aabb>::default())
-            .add_system_set_to_stage(
+            .add_event::<constraints::JointBroken>()
+            .add_stage_after(
+                CoreStage::PostUpdate,
+                PhysicsStage,
+                SystemStage::parallel().with_run_criteria(run_physics_step),
+            )
+            .add_system_to_stage(
                 CoreStage::PostUpdate,
+                interpolation::restore_pose_system.after(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                interpolation::interpolate_system.label(Update::Interpolate),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                transform::propagate_children_system.after(Update::Interpolate),
+            )
+            .add_system_set_to_stage(
+                PhysicsStage,
                 SystemSet::new()
                     .label(Physics::First)
                     .with_run_criteria(run_disabled_physics)
                     .with_system(steponce_pre_system),
             )
+            // Once per step, find the potential pairs for every substep
             .add_system_set_to_stage(
-                CoreStage::PostUpdate,
+                PhysicsStage,
                 SystemSet::new()
                     .label(Physics::PreUpdate)
                     .after(Physics::First)
-                    .after(TransformSystem::TransformPropagate)
-                    .after(BoundingSystem::UpdateBounds)
-                    .with_run_criteria(run_physics)
-                    .with_system(update_time_system)
+                    .with_run_criteria(run_step_start)
+                    .with_system(interpolation::store_previous_system.label(PreUpdate::First))
                     .with_system(spawn_sphere.label(PreUpdate::First))
-                    .with_system(spawn_box.label(PreUpdate::First)), //.with_system(update_aabb),
+                    .with_system(spawn_box.label(PreUpdate::First))
+                    .with_system(dynamics::kinematic_target_system.label(PreUpdate::First))
+                    .with_system(
+                        update_aabb::<ColliderSphere>
+                            .label(PreUpdate::Second)
+                            .after(PreUpdate::First),
+                    )
+                    .with_system(
+                        update_aabb::<ColliderBox>
+                            .label(PreUpdate::Second)
+                            .after(PreUpdate::First),
+                    )
+                    .with_system(
+                        broad::broadphase_system
+                            .label(Update::Broadphase)
+                            .after(PreUpdate::Second),
+                    ),
             )
+            // Every substep
             .add_system_set_to_stage(
-                CoreStage::PostUpdate,
+                PhysicsStage,
                 SystemSet::new()
                     .label(Physics::Update)
                     .after(Physics::PreUpdate)
                     .with_run_criteria(run_physics)
                     .with_system(dynamics::dynamics_gravity_system.label(Update::Dynamics))
                     .with_system(
-                        broad::broadphase_system
-                            .label(Update::Broadphase)
+                        narrow::narrowphase_system
+                            .label(Update::Narrowphase)
                             .after(Update::Dynamics),
                     )
-                    // Narrowphase Static and Dynamic collision detection would go here
-                    // they part of diffferent set since they use different run_criteria
                     .with_system(
-                        //resolve_contact::resolve_contact_system_ordered
-                        resolve_contact::resolve_contact_system
-                            .label(Update::ResolveContact)
+                        island::build_islands_system
+                            .label(Update::Islands)
                             .after(Update::Narrowphase),
                     )
                     .with_system(
-                        constraints::constraint_penetration::pre_solve_system
-                            .label(Update::ConstraintsPreSolve)
-                            .after(Update::ResolveContact),
-                    )
-                    .with_system(
-                        constraints::constraint_penetration::solve_system
-                            .label(Update::ConstraintsSolve)
-                            .after(Update::ConstraintsPreSolve),
-                    )
-                    .with_system(
-                        transform::update_local_tranform
-                            .label(Update::Transform)
-                            .after(Update::ConstraintsSolve),
+                        resolve_contact::resolve_contact_system
+                            .label(Update::ResolveContact)
+                            .after(Update::Islands),
                     ), // .add_system_set_to_stage(
                        //     CoreStage::PreUpdate,
                        //     SystemSet::new()
@@ -164,55 +288,40 @@ impl Plugin for PhysicsPlugin {
                        //         .with_system(manifold_remove_expired_system),
                        // );
             )
-            //TODO: Wish i could use run criteria on sub systems, but its not allowed
-            // Static Collision Detection
-            .add_system_set_to_stage(
-                CoreStage::PostUpdate,
-                SystemSet::new()
-                    .label(Physics::Update)
-                    .after(Physics::PreUpdate)
-                    .with_run_criteria(run_physics)
-                    .with_run_criteria(run_static)
-                    .with_system(
-                        narrow::narrowphase_system_static
-                            .label(Update::Narrowphase)
-                            .after(Update::Broadphase),
-                    ),
-            )
-            // Dynamic Collision Detection
-            .add_system_set_to_stage(
-                CoreStage::PostUpdate,
-                SystemSet::new()
-                    .label(Physics::Update)
-                    .after(Physics::PreUpdate)
-                    .with_run_criteria(run_physics)
-                    .with_run_criteria(run_dynamic)
-                    .with_system(
-                        narrow::narrowphase_system_dynamic
-                            .label(Update::Narrowphase)
-                            .after(Update::Broadphase),
-                    ),
-            )
+            // Once per step, after the last substep
             .add_system_set_to_stage(
-                CoreStage::PostUpdate,
+                PhysicsStage,
                 SystemSet::new()
                     .label(Physics::PostUpdate)
                     .after(Physics::Update)
-                    .with_run_criteria(run_physics)
+                    .with_run_criteria(run_step_end)
+                    .with_system(transform::update_local_tranform.label(Update::Transform))
+                    .with_system(interpolation::store_current_system.after(Update::Transform))
+                    .with_system(island::sleep_system.label(Update::Sleep))
+                    .with_system(dynamics::clear_forces_system)
                     .with_system(steponce_post_system),
-            );
+            )
+            .add_constraint::<ConstraintPenetration>()
+            .add_constraint::<DistanceJoint>()
+            .add_constraint::<HingeJoint>()
+            .add_constraint::<MotorJoint>()
+            .add_constraint::<ConeTwistJoint>()
+            .add_constraint::<FixedJoint>()
+            .add_constraint::<PrismaticJoint>()
+            .add_constraint::<TargetFollow>();
     }
 }
 
 pub fn spawn_sphere(
     mut commands: Commands,
-    mut query: Query<(Entity, &ColliderSphere, &mut Body), Added<ColliderSphere>>,
+    mut query: Query<(Entity, &ColliderSphere, &mut Body, &GlobalTransform), Added<ColliderSphere>>,
 ) {
-    for (e, sphere, mut body) in query.iter_mut() {
+    for (e, sphere, mut body, trans) in query.iter_mut() {
         commands
             .entity(e)
             .insert(sphere.shape_type())
-            .insert(Bounded::<Aabb>::default());
+            .insert(sphere.aabb(trans.rotation))
+            .insert(SleepTimer::default());
 
         body.center_of_mass = Vec3::ZERO;
         body.inertia_tensor =
@@ -222,13 +331,14 @@ pub fn spawn_sphere(
 
 pub fn spawn_box(
     mut commands: Commands,
-    mut query: Query<(Entity, &ColliderBox, &mut Body), Added<ColliderBox>>,
+    mut query: Query<(Entity, &ColliderBox, &mut Body, &GlobalTransform), Added<ColliderBox>>,
 ) {
-    for (e, b, mut body) in query.iter_mut() {
+    for (e, b, mut body, trans) in query.iter_mut() {
         commands
             .entity(e)
             .insert(b.shape_type())
-            .insert(Bounded::<Aabb>::default());
+            .insert(b.aabb(trans.rotation))
+            .insert(SleepTimer::default());
 
         // inertia tensor for box centered around zero
         let aabb = Aabb::compute_aabb(&b.points);
@@ -255,17 +365,38 @@ pub fn spawn_box(
     }
 }
 
-pub fn update_aabb(mut query: Query<(&Body, &mut Aabb)>, pt: Res<PhysicsTime>) {
-    for (body, mut aabb) in query.iter_mut() {
+/// Computes the broadphase bounds from the collider, swept by the motion of the body over the whole
+/// step so the pairs stay valid for every substep
+pub fn update_aabb<T: Collider + Component>(
+    mut query: Query<(&T, &Body, &GlobalTransform, &mut Aabb), Without<Sleeping>>,
+    pt: Res<PhysicsTime>,
+) {
+    for (collider, body, trans, mut aabb) in query.iter_mut() {
+        let mut bounds = collider.aabb(trans.rotation);
+        let radius = bounds.minimums().abs().max(bounds.maximums().abs()).length();
+
         // expand the bounds by the linear velocity
-        aabb.expand_velocity(body.linear_velocity * pt.time);
+        bounds.expand_velocity(body.linear_velocity * pt.step_time);
+
+        // and by the furthest any point can travel while rotating
+        bounds.expand(body.angular_velocity.length() * pt.step_time * radius);
+
         const BOUNDS_EPS: f32 = 0.01;
-        aabb.expand_velocity(Vec3::splat(BOUNDS_EPS));
+        bounds.expand(BOUNDS_EPS);
+
+        // only write when changed, so resting and static bodies aren't flagged every frame
+        if *aabb != bounds {
+            *aabb = bounds;
+        }
     }
 }
 
+fn physics_enabled(config: &PhysicsConfig) -> bool {
+    config.time_dilation != 0.0 && config.enabled
+}
+
 fn run_physics(config: Res<PhysicsConfig>) -> ShouldRun {
-    if config.time_dilation != 0.0 && config.enabled {
+    if physics_enabled(&config) {
         ShouldRun::Yes
     } else {
         ShouldRun::No
@@ -273,42 +404,110 @@ fn run_physics(config: Res<PhysicsConfig>) -> ShouldRun {
 }
 
 fn run_disabled_physics(config: Res<PhysicsConfig>) -> ShouldRun {
-    if config.time_dilation != 0.0 && config.enabled {
+    if physics_enabled(&config) {
         ShouldRun::No
     } else {
         ShouldRun::Yes
     }
 }
 
-fn run_static(config: Res<PhysicsConfig>) -> ShouldRun {
-    if config.collision_dection == CollisionDetection::Static {
+fn run_step_start(config: Res<PhysicsConfig>, pt: Res<PhysicsTime>) -> ShouldRun {
+    if physics_enabled(&config) && pt.substep == 0 {
         ShouldRun::Yes
     } else {
         ShouldRun::No
     }
 }
 
-fn run_dynamic(config: Res<PhysicsConfig>) -> ShouldRun {
-    if config.collision_dection == CollisionDetection::Dynamic {
+fn run_step_end(config: Res<PhysicsConfig>, pt: Res<PhysicsTime>) -> ShouldRun {
+    if physics_enabled(&config) && pt.substep + 1 >= config.substeps {
         ShouldRun::Yes
     } else {
         ShouldRun::No
     }
 }
 
-fn update_time_system(time: Res<Time>, config: Res<PhysicsConfig>, mut pt: ResMut<PhysicsTime>) {
-    // NOTE: I am avoiding using fixed time, thats because
-    // we want to develop the hot path of the physics system
-    pt.time = (time.delta_seconds() * config.time_dilation).clamp(0.0, 0.1);
+// Stage criteria for the physics stage, runs the stage once per substep needed this frame
+fn run_physics_step(
+    time: Res<Time>,
+    config: Res<PhysicsConfig>,
+    mut pt: ResMut<PhysicsTime>,
+) -> ShouldRun {
+    // first check this frame, work out how much time there is to simulate
+    let mut delta = None;
+    if pt.last_update != time.last_update() {
+        pt.last_update = time.last_update();
+        delta = Some(time.delta_seconds());
+    }
+    next_substep(&config, &mut pt, delta)
+}
+
+// Moves on to the next substep if there is time left this frame, delta is the frame's time on the
+// first check of each frame
+fn next_substep(config: &PhysicsConfig, pt: &mut PhysicsTime, delta: Option<f32>) -> ShouldRun {
+    let substeps = config.substeps.max(1);
+
+    if let Some(delta) = delta {
+        pt.steps = 0;
+        pt.substep = 0;
+
+        let delta = (delta * config.time_dilation).max(0.0);
+        match config.timestep {
+            Timestep::Variable => {
+                pt.step_time = delta.min(0.1);
+                pt.accumulator = 0.0;
+            }
+            Timestep::Fixed => pt.step_time = 1.0 / config.fixed_rate,
+        }
+        pt.time = pt.step_time / substeps as f32;
+
+        // when disabled we still run a single pass, so step once can turn physics back on
+        if !physics_enabled(config) {
+            return ShouldRun::Yes;
+        }
+
+        if config.timestep == Timestep::Fixed {
+            let max_time = pt.step_time * config.max_steps as f32;
+            pt.accumulator = (pt.accumulator + delta).min(max_time);
+        }
+    }
+
+    // keep going through the substeps of the current step
+    if pt.steps > 0 && pt.substep + 1 < substeps {
+        pt.substep += 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+
+    // start the next step if we have time for it
+    let step_ready = match config.timestep {
+        Timestep::Variable => pt.steps == 0,
+        Timestep::Fixed => pt.accumulator >= pt.step_time,
+    };
+    if step_ready {
+        if config.timestep == Timestep::Fixed {
+            pt.accumulator -= pt.step_time;
+        }
+        pt.steps += 1;
+        pt.substep = 0;
+        return ShouldRun::YesAndCheckAgain;
+    }
+
+    pt.alpha = match config.timestep {
+        Timestep::Variable => 1.0,
+        Timestep::Fixed => pt.accumulator / pt.step_time,
+    };
+    ShouldRun::No
 }
 
 pub fn steponce_pre_system(
     mut step_ev: EventReader<StepOnceEvent>,
     mut config: ResMut<PhysicsConfig>,
+    mut pt: ResMut<PhysicsTime>,
 ) {
     for _ in step_ev.iter() {
-        info!("here");
         config.enabled = true;
+        // make sure a fixed step is ready to run next frame
+        pt.accumulator = pt.accumulator.max(pt.step_time);
     }
 }
 
@@ -320,3 +519,92 @@ pub fn steponce_post_system(
         config.enabled = false;
     }
 }
+
+// Runs the stage criteria through one frame, returns how many times the stage ran
+#[cfg(test)]
+fn test_frame(config: &PhysicsConfig, pt: &mut PhysicsTime, delta: f32) -> usize {
+    let mut runs = 0;
+    let mut delta = Some(delta);
+    loop {
+        match next_substep(config, pt, delta.take()) {
+            ShouldRun::Yes => return runs + 1,
+            ShouldRun::YesAndCheckAgain => runs += 1,
+            _ => return runs,
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_fixed_config(substeps: usize) -> PhysicsConfig {
+    PhysicsConfig {
+        timestep: Timestep::Fixed,
+        // a power of two, so the step time and the deltas below are exact
+        fixed_rate: 64.0,
+        max_steps: 5,
+        substeps,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_fixed_step_accumulator() {
+    let config = test_fixed_config(1);
+    let mut pt = PhysicsTime::default();
+
+    // two whole steps
+    assert_eq!(test_frame(&config, &mut pt, 2.0 / 64.0), 2);
+    assert_eq!(pt.steps(), 2);
+    assert_eq!(pt.alpha(), 0.0);
+
+    // one and a half steps, the half is carried over and shows up as the alpha
+    assert_eq!(test_frame(&config, &mut pt, 1.5 / 64.0), 1);
+    assert_eq!(pt.alpha(), 0.5);
+
+    // the carried half makes up a whole step with this one
+    assert_eq!(test_frame(&config, &mut pt, 0.5 / 64.0), 1);
+    assert_eq!(pt.alpha(), 0.0);
+
+    // not enough for a step, nothing runs
+    assert_eq!(test_frame(&config, &mut pt, 0.25 / 64.0), 0);
+    assert_eq!(pt.steps(), 0);
+    assert_eq!(pt.alpha(), 0.25);
+}
+
+#[test]
+fn test_fixed_step_max_steps() {
+    let config = test_fixed_config(1);
+    let mut pt = PhysicsTime::default();
+
+    // a long hitch only runs max_steps, the rest of the time is dropped
+    assert_eq!(test_frame(&config, &mut pt, 1.0), 5);
+    assert_eq!(pt.steps(), 5);
+    assert_eq!(pt.alpha(), 0.0);
+
+    assert_eq!(test_frame(&config, &mut pt, 0.0), 0);
+}
+
+#[test]
+fn test_fixed_step_substeps() {
+    let config = test_fixed_config(4);
+    let mut pt = PhysicsTime::default();
+
+    // every step runs the stage once per substep, each a quarter of the step
+    assert_eq!(test_frame(&config, &mut pt, 2.0 / 64.0), 8);
+    assert_eq!(pt.steps(), 2);
+    assert_eq!(pt.step_time(), 1.0 / 64.0);
+    assert_eq!(pt.time(), 0.25 / 64.0);
+}
+
+#[test]
+fn test_variable_step() {
+    let config = PhysicsConfig::default();
+    let mut pt = PhysicsTime::default();
+
+    // a single step of the frame's time, clamped so a hitch can't blow up the simulation
+    assert_eq!(test_frame(&config, &mut pt, 0.5), 1);
+    assert_eq!(pt.step_time(), 0.1);
+    assert_eq!(pt.alpha(), 1.0);
+
+    assert_eq!(test_frame(&config, &mut pt, 0.05), 1);
+    assert_eq!(pt.step_time(), 0.05);
+}
@@ -2,22 +2,87 @@ use bevy::prelude::*;
 
 use crate::{primitives::*, intersect, bounds::aabb::Aabb};
 
+/// Static and sleeping bodies sorted on the x axis, these are only rebuilt when one of them spawns,
+/// moves, falls asleep, wakes or is removed, so we dont have to collect and sort them every frame
+#[derive(Default)]
+pub struct StaticBroadphase {
+    list: Vec<(Entity, Aabb)>,
+    /// widest static aabb on the x axis, bounds how far back we need to search the sorted list
+    max_width: f32,
+}
+
+impl StaticBroadphase {
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    fn rebuild(&mut self, list: Vec<(Entity, Aabb)>) {
+        self.list = list;
+        self.list.sort_unstable_by(cmp_x_axis);
+        self.max_width = self.list.iter().fold(0.0, |width, (_, aabb)| {
+            f32::max(width, aabb.maximums().x - aabb.minimums().x)
+        });
+    }
+}
+
+/// Potential collision pairs found by the broadphase, these are reused by every substep of a step
+#[derive(Default)]
+pub struct BroadphasePairs {
+    pub pairs: Vec<BroadContact>,
+}
+
 // The board phase is responsible for pruning the search space of possable collisions
 // I have tried different approaches, and I am sure I will try a few more
 // So far this simple approach has been the fastest
 // TODO: Figure out way to search two axis thats actually faster or bite the bullet and try some space partitioning
 pub fn broadphase_system(
-    mut broad_contacts: EventWriter<BroadContact>,
-    query: Query<(Entity, &Aabb, &GlobalTransform)>,
+    mut broad_contacts: ResMut<BroadphasePairs>,
+    mut statics: ResMut<StaticBroadphase>,
+    query: Query<(Entity, &Body, &Aabb, &GlobalTransform, Option<&Sleeping>)>,
+    changed: Query<(&Body, Option<&Sleeping>), Or<(Changed<Aabb>, Changed<GlobalTransform>)>>,
+    fell_asleep: Query<Entity, Added<Sleeping>>,
+    woken: RemovedComponents<Sleeping>,
+    removed: RemovedComponents<Body>,
 ) {
+    broad_contacts.pairs.clear();
+
     // TODO: Yes, we are copying the array out here, only way to sort it
     // Ideally we would keep the array around, it should already near sorted
-    let mut list = query.iter()
-        .map(|(e, aabb, t)| {
-             (e, Aabb::from_extents(t.translation + aabb.minimums(), t.translation + aabb.maximums()) )
+    let mut static_count = 0;
+    let mut list = query
+        .iter()
+        .filter_map(|(e, body, aabb, t, sleeping)| {
+            if is_fixed(body, sleeping) {
+                static_count += 1;
+                return None;
+            }
+            Some((e, world_aabb(aabb, t)))
         })
         .collect::<Vec<_>>();
-    
+
+    // Only rebuild the static set if a static or sleeping body has spawned, moved, changed state
+    // or been removed
+    let rebuild = static_count != statics.len()
+        || changed.iter().any(|(body, sleeping)| is_fixed(body, sleeping))
+        || fell_asleep.iter().next().is_some()
+        || woken.iter().next().is_some()
+        || removed
+            .iter()
+            .any(|e| statics.list.iter().any(|(s, _)| *s == e));
+    if rebuild {
+        statics.rebuild(
+            query
+                .iter()
+                .filter(|(_, body, _, _, sleeping)| is_fixed(body, *sleeping))
+                .map(|(e, _, aabb, t, _)| (e, world_aabb(aabb, t)))
+                .collect(),
+        );
+    }
+
     // Sort the array on currently selected sorting axis
     list.sort_unstable_by(cmp_x_axis);
 
@@ -30,13 +95,35 @@ pub fn broadphase_system(
                 break;
             }
             if intersect::aabb_aabb_intersect(aabb_a, aabb_b) {
-                broad_contacts.send(BroadContact { a: *a, b: *b });
+                broad_contacts.pairs.push(BroadContact { a: *a, b: *b });
             }
         }
 
+        // Test against the static set, anything starting further back than the widest static
+        // can't reach us, so we can skip straight past it
+        let start = statics
+            .list
+            .partition_point(|(_, s)| s.minimums().x < aabb_a.minimums().x - statics.max_width);
+        for (b, aabb_b) in statics.list.iter().skip(start) {
+            if aabb_b.minimums().x > aabb_a.maximums().x {
+                break;
+            }
+            if intersect::aabb_aabb_intersect(aabb_a, aabb_b) {
+                broad_contacts.pairs.push(BroadContact { a: *a, b: *b });
+            }
+        }
     }
 }
 
+// Static and sleeping bodies don't move, so they only need testing against moving bodies
+fn is_fixed(body: &Body, sleeping: Option<&Sleeping>) -> bool {
+    body.is_static() || sleeping.is_some()
+}
+
+fn world_aabb(aabb: &Aabb, t: &GlobalTransform) -> Aabb {
+    Aabb::from_extents(t.translation + aabb.minimums(), t.translation + aabb.maximums())
+}
+
 fn cmp_x_axis( a: &(Entity, Aabb), b: &(Entity, Aabb)) -> std::cmp::Ordering {
     // Sort on minimum value along either x, y, or z axis
     let min_a = a.1.minimums().x;
@@ -4,16 +4,104 @@ use crate::{PhysicsConfig, primitives::*, PhysicsTime};
 pub fn dynamics_gravity_system(
     config: Res<PhysicsConfig>,
     pt: Res<PhysicsTime>,
-    mut query: Query<&mut Body>,
+    mut query: Query<
+        (
+            &mut Body,
+            &GlobalTransform,
+            Option<&GravityScale>,
+            Option<&ExternalForce>,
+            Option<&ExternalTorque>,
+            Option<&LockedAxes>,
+        ),
+        Without<Sleeping>,
+    >,
+    fields: Query<(&GravityField, &GlobalTransform)>,
 ) {
-    for mut body in query.iter_mut() {
-        if body.inv_mass != 0.0 {
+    let fields = fields.iter().collect::<Vec<_>>();
+
+    for (mut body, trans, scale, force, torque, locks) in query.iter_mut() {
+        let locks = locks.copied().unwrap_or_default();
+        if !body.has_infinite_mass() {
+            // sum the global gravity with every field reaching the centre of mass
+            let position = body.centre_of_mass_world(trans);
+            let gravity = fields
+                .iter()
+                .fold(config.gravity, |g, (field, field_trans)| {
+                    g + field.acceleration(field_trans, position)
+                })
+                * scale.map_or(1.0, |s| s.0);
+
             // gravity needs to be an impulse
             // I = dp, F = dp/dt => dp = F * dt => I = F * dt
             // F = mgs
             let mass = 1.0 / body.inv_mass;
-            let impluse_gravity = config.gravity * mass * pt.time;
-            body.apply_impulse_linear(impluse_gravity);
+            let impluse_gravity = gravity * mass * pt.time;
+            body.apply_impulse_linear(impluse_gravity, locks);
+
+            // external forces are turned into impulses the same way, off centre forces also
+            // produce a torque through the centre of mass
+            if let Some(force) = force {
+                let impulse = force.world_force(trans.rotation) * pt.time;
+                match force.point {
+                    Some(point) => {
+                        let world_point = body.local_to_world(trans, point);
+                        body.apply_impulse(world_point, impulse, trans, locks);
+                    }
+                    None => body.apply_impulse_linear(impulse, locks),
+                }
+            }
+
+            // dL = T * dt
+            if let Some(torque) = torque {
+                let impulse = torque.world_torque(trans.rotation) * pt.time;
+                body.apply_impulse_angular(impulse, trans, locks);
+            }
+
+            body.apply_damping(pt.time);
+        }
+    }
+}
+
+/// One-shot forces and torques are removed once a whole step has applied them
+pub fn clear_forces_system(
+    mut commands: Commands,
+    forces: Query<(Entity, &ExternalForce), Without<Sleeping>>,
+    torques: Query<(Entity, &ExternalTorque), Without<Sleeping>>,
+) {
+    for (e, force) in forces.iter() {
+        if !force.persistent {
+            commands.entity(e).remove::<ExternalForce>();
+        }
+    }
+    for (e, torque) in torques.iter() {
+        if !torque.persistent {
+            commands.entity(e).remove::<ExternalTorque>();
+        }
+    }
+}
+
+/// Sets kinematic velocities so they reach their target pose by the end of the step
+pub fn kinematic_target_system(
+    pt: Res<PhysicsTime>,
+    mut query: Query<(&mut Body, &GlobalTransform, &KinematicTarget)>,
+) {
+    let dt = pt.step_time();
+    if dt <= 0.0 {
+        return;
+    }
+
+    for (mut body, trans, target) in query.iter_mut() {
+        if !body.is_kinematic() {
+            continue;
+        }
+        body.linear_velocity = (target.translation - trans.translation) / dt;
+
+        // take the shortest way round
+        let mut dq = target.rotation * trans.rotation.conjugate();
+        if dq.w < 0.0 {
+            dq = -dq;
         }
+        let (axis, angle) = dq.to_axis_angle();
+        body.angular_velocity = axis * angle / dt;
     }
 }
@@ -0,0 +1,61 @@
+use bevy::prelude::*;
+
+use crate::{primitives::PhysicsInterpolation, PhysicsTime};
+
+// The rendered pose is written to GlobalTransform, so before physics runs again we put the
+// simulated pose back, unless the Transform was moved by something other than physics
+pub fn restore_pose_system(
+    mut query: Query<(&Transform, &mut GlobalTransform, &mut PhysicsInterpolation)>,
+) {
+    for (t, mut gt, mut interp) in query.iter_mut() {
+        if t.translation == interp.local_translation && t.rotation == interp.local_rotation {
+            if gt.translation != interp.current_translation || gt.rotation != interp.current_rotation {
+                gt.translation = interp.current_translation;
+                gt.rotation = interp.current_rotation;
+            }
+        } else {
+            // teleported, snap to the new pose instead of blending to it
+            interp.previous_translation = gt.translation;
+            interp.previous_rotation = gt.rotation;
+            interp.current_translation = gt.translation;
+            interp.current_rotation = gt.rotation;
+            interp.local_translation = t.translation;
+            interp.local_rotation = t.rotation;
+        }
+    }
+}
+
+pub fn store_previous_system(mut query: Query<(&GlobalTransform, &mut PhysicsInterpolation)>) {
+    for (gt, mut interp) in query.iter_mut() {
+        interp.previous_translation = gt.translation;
+        interp.previous_rotation = gt.rotation;
+    }
+}
+
+pub fn store_current_system(
+    mut query: Query<(&GlobalTransform, &Transform, &mut PhysicsInterpolation)>,
+) {
+    for (gt, t, mut interp) in query.iter_mut() {
+        interp.current_translation = gt.translation;
+        interp.current_rotation = gt.rotation;
+        interp.local_translation = t.translation;
+        interp.local_rotation = t.rotation;
+    }
+}
+
+pub fn interpolate_system(
+    pt: Res<PhysicsTime>,
+    mut query: Query<(&mut GlobalTransform, &PhysicsInterpolation)>,
+) {
+    let alpha = pt.alpha();
+    for (mut gt, interp) in query.iter_mut() {
+        let translation = interp
+            .previous_translation
+            .lerp(interp.current_translation, alpha);
+        let rotation = interp.previous_rotation.slerp(interp.current_rotation, alpha);
+        if gt.translation != translation || gt.rotation != rotation {
+            gt.translation = translation;
+            gt.rotation = rotation;
+        }
+    }
+}
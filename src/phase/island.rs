@@ -0,0 +1,373 @@
+use bevy::{
+    prelude::*,
+    tasks::ComputeTaskPool,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    constraints::Constraint,
+    primitives::*,
+    PhysicsConfig, PhysicsTime,
+};
+
+/// Groups of awake bodies connected by contacts and constraints, bodies in different islands can't
+/// affect each other this step. Static and kinematic bodies never join an island, sleeping bodies
+/// only join when something touches them.
+#[derive(Default)]
+pub struct Islands {
+    pub islands: Vec<Vec<Entity>>,
+    /// Kinematic bodies move on their own, so they are kept out of every island
+    pub kinematic: Vec<Entity>,
+    index: HashMap<Entity, usize>,
+    // dynamic bodies left asleep this step, nothing acting on them may be solved
+    sleeping: HashSet<Entity>,
+}
+
+impl Islands {
+    /// Island the body is in, None for static and sleeping bodies
+    pub fn island_of(&self, e: Entity) -> Option<usize> {
+        self.index.get(&e).copied()
+    }
+
+    /// Sorts items into the island of the bodies they act on, keeping their order. Items only
+    /// touching static or sleeping bodies are dropped, as are any touching a body that is still
+    /// asleep, those are never in the same island as the body they'd push.
+    pub fn group<T>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        handles: impl Fn(&T) -> (Entity, Entity),
+    ) -> Vec<Vec<T>> {
+        let mut groups = self.islands.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        for item in items {
+            let (a, b) = handles(&item);
+            let asleep = self.sleeping.contains(&a) || self.sleeping.contains(&b);
+            debug_assert!(
+                !asleep || (self.island_of(a).is_none() && self.island_of(b).is_none()),
+                "an island reaches a sleeping body"
+            );
+            if asleep {
+                continue;
+            }
+            if let Some(island) = self.island_of(a).or_else(|| self.island_of(b)) {
+                groups[island].push(item);
+            }
+        }
+        groups
+    }
+}
+
+/// Runs the function once per island group, spread over the compute task pool when parallel.
+/// Islands never share a moving body, so both paths give the same results.
+pub fn for_each_island<T: Send>(
+    pool: &ComputeTaskPool,
+    parallel: bool,
+    groups: Vec<T>,
+    f: impl Fn(T) + Send + Sync,
+) {
+    if parallel && groups.len() > 1 {
+        let f = &f;
+        pool.scope(|scope| {
+            for group in groups {
+                scope.spawn(async move { f(group) });
+            }
+        });
+    } else {
+        groups.into_iter().for_each(f);
+    }
+}
+
+/// Bodies held together by joints this step, collected before the islands are built
+#[derive(Default)]
+pub struct ConstraintEdges(Vec<(Entity, Entity)>);
+
+pub fn constraint_edges_system<T: Constraint + Component>(
+    mut edges: ResMut<ConstraintEdges>,
+    query: Query<&T>,
+) {
+    edges
+        .0
+        .extend(query.iter().map(|constraint| constraint.handles()));
+}
+
+// Disjoint set over the body indices, with path halving
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a != b {
+            self.parent[b] = a;
+        }
+    }
+}
+
+pub fn build_islands_system(
+    mut commands: Commands,
+    mut islands: ResMut<Islands>,
+    mut joints: ResMut<ConstraintEdges>,
+    mut contacts: EventReader<Contact>,
+    mut manifold_contacts: EventReader<ManifoldContactEvent>,
+    bodies: Query<(Entity, &Body, Option<&Sleeping>)>,
+) {
+    // index every awake dynamic body, moving kinematic bodies can wake others but never join
+    let mut index = HashMap::default();
+    let mut entities = Vec::new();
+    let mut movers = HashSet::default();
+    let mut kinematic = Vec::new();
+    let mut asleep = HashSet::default();
+    for (e, body, sleeping) in bodies.iter() {
+        if body.is_kinematic() {
+            kinematic.push(e);
+            if body.linear_velocity != Vec3::ZERO || body.angular_velocity != Vec3::ZERO {
+                movers.insert(e);
+            }
+        }
+        if body.has_infinite_mass() {
+            continue;
+        }
+        if sleeping.is_some() {
+            asleep.insert(e);
+            continue;
+        }
+        index.insert(e, entities.len());
+        entities.push(e);
+    }
+
+    let mut edges = contacts
+        .iter()
+        .chain(manifold_contacts.iter().map(|manifold| &manifold.0))
+        .map(|contact| (contact.entity_a, contact.entity_b))
+        .collect::<Vec<_>>();
+    edges.append(&mut joints.0);
+
+    // an awake or moving body touching a sleeping one wakes it, it joins the island straight away so the
+    // impulses it gets this step stay inside one island. Waking spreads along chains of sleeping
+    // bodies whatever order their edges are in, so keep going until nothing else wakes
+    let mut woken = Vec::new();
+    let awake =
+        |index: &HashMap<Entity, usize>, e: &Entity| index.contains_key(e) || movers.contains(e);
+    loop {
+        let len = entities.len();
+        for (a, b) in edges.iter() {
+            match (awake(&index, a), awake(&index, b)) {
+                (true, false) => woken.extend(wake(&mut commands, &bodies, *b)),
+                (false, true) => woken.extend(wake(&mut commands, &bodies, *a)),
+                _ => {}
+            }
+            for e in woken.drain(..) {
+                asleep.remove(&e);
+                index.insert(e, entities.len());
+                entities.push(e);
+            }
+        }
+        if entities.len() == len {
+            break;
+        }
+    }
+
+    let mut sets = UnionFind::new(entities.len());
+    for (a, b) in edges.iter() {
+        if let (Some(a), Some(b)) = (index.get(a), index.get(b)) {
+            sets.union(*a, *b);
+        }
+    }
+
+    let mut roots = HashMap::default();
+    islands.islands.clear();
+    islands.index.clear();
+    islands.kinematic = kinematic;
+    islands.sleeping = asleep;
+    for (i, e) in entities.iter().enumerate() {
+        let root = sets.find(i);
+        let island = *roots.entry(root).or_insert_with(|| {
+            islands.islands.push(Vec::new());
+            islands.islands.len() - 1
+        });
+        islands.islands[island].push(*e);
+        islands.index.insert(*e, island);
+    }
+}
+
+fn wake(
+    commands: &mut Commands,
+    bodies: &Query<(Entity, &Body, Option<&Sleeping>)>,
+    e: Entity,
+) -> Option<Entity> {
+    if let Ok((_, _, Some(_))) = bodies.get(e) {
+        commands.entity(e).remove::<Sleeping>();
+        return Some(e);
+    }
+    None
+}
+
+fn is_resting(config: &PhysicsConfig, body: &Body) -> bool {
+    body.linear_velocity.length_squared() < config.sleep_linear_threshold.powi(2)
+        && body.angular_velocity.length_squared() < config.sleep_angular_threshold.powi(2)
+}
+
+pub fn sleep_system(
+    mut commands: Commands,
+    config: Res<PhysicsConfig>,
+    pt: Res<PhysicsTime>,
+    islands: Res<Islands>,
+    mut awake: Query<(&mut Body, &GlobalTransform, &mut SleepTimer), Without<Sleeping>>,
+    sleeping: Query<(Entity, &Body, &GlobalTransform, &Sleeping)>,
+    forced: Query<
+        Entity,
+        (
+            With<Sleeping>,
+            Or<(
+                Changed<ExternalForce>,
+                Changed<ExternalTorque>,
+                Changed<GravityScale>,
+            )>,
+        ),
+    >,
+    fields: Query<
+        (&GravityField, &GlobalTransform),
+        Or<(Changed<GravityField>, Changed<GlobalTransform>)>,
+    >,
+) {
+    // wake anything that has been pushed or moved since it fell asleep, or that a field which
+    // has been added, changed or moved now reaches
+    let fields = fields.iter().collect::<Vec<_>>();
+    for (e, body, trans, sleep) in sleeping.iter() {
+        let position = body.centre_of_mass_world(trans);
+        if !config.sleep_enabled
+            || forced.get(e).is_ok()
+            || fields
+                .iter()
+                .any(|(field, field_trans)| field.acceleration(field_trans, position) != Vec3::ZERO)
+            || !is_resting(&config, body)
+            || trans.translation != sleep.translation
+            || trans.rotation != sleep.rotation
+        {
+            commands.entity(e).remove::<Sleeping>();
+        }
+    }
+
+    if !config.sleep_enabled {
+        return;
+    }
+
+    for (body, _, mut timer) in awake.iter_mut() {
+        if is_resting(&config, &body) {
+            timer.0 += pt.step_time();
+        } else {
+            timer.0 = 0.0;
+        }
+    }
+
+    // an island only sleeps once every body in it has been resting long enough
+    for island in islands.islands.iter() {
+        let rested = island.iter().all(|e| {
+            awake
+                .get(*e)
+                .map_or(false, |(_, _, timer)| timer.0 >= config.sleep_time)
+        });
+        if !rested {
+            continue;
+        }
+
+        for e in island.iter() {
+            if let Ok((mut body, trans, mut timer)) = awake.get_mut(*e) {
+                body.linear_velocity = Vec3::ZERO;
+                body.angular_velocity = Vec3::ZERO;
+                timer.0 = 0.0;
+                commands.entity(*e).insert(Sleeping {
+                    translation: trans.translation,
+                    rotation: trans.rotation,
+                });
+            }
+        }
+    }
+}
+
+#[test]
+fn test_wake_spreads_along_sleeping_chain() {
+    use bevy::app::Events;
+
+    let mut world = World::default();
+    world.insert_resource(Islands::default());
+    world.insert_resource(Events::<Contact>::default());
+    world.insert_resource(Events::<ManifoldContactEvent>::default());
+
+    let sleeping = Sleeping {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+    };
+    let a = world.spawn().insert(Body::default()).id();
+    let b = world
+        .spawn()
+        .insert_bundle((Body::default(), sleeping))
+        .id();
+    let c = world
+        .spawn()
+        .insert_bundle((Body::default(), sleeping))
+        .id();
+
+    // the end of the chain is listed first, it only wakes once b has
+    world.insert_resource(ConstraintEdges(vec![(c, b), (a, b)]));
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(build_islands_system);
+    stage.run(&mut world);
+
+    let islands = world.get_resource::<Islands>().unwrap();
+    assert_eq!(islands.islands.len(), 1);
+    for e in [b, c] {
+        assert_eq!(islands.island_of(e), islands.island_of(a));
+        assert!(world.get::<Sleeping>(e).is_none());
+    }
+    assert!(islands.sleeping.is_empty());
+}
+
+#[test]
+fn test_new_gravity_field_wakes_sleeping_body() {
+    let mut world = World::default();
+    world.insert_resource(PhysicsConfig::default());
+    world.insert_resource(PhysicsTime::default());
+    world.insert_resource(Islands::default());
+
+    let sleeping = Sleeping {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+    };
+    let body = world
+        .spawn()
+        .insert_bundle((Body::default(), GlobalTransform::identity(), sleeping))
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(sleep_system);
+    stage.run(&mut world);
+    assert!(world.get::<Sleeping>(body).is_some());
+
+    // a low gravity room placed around the resting body
+    world.spawn().insert_bundle((
+        GravityField::Directional {
+            acceleration: Vec3::Y * 5.0,
+            half_extents: Vec3::ONE,
+        },
+        GlobalTransform::identity(),
+    ));
+    stage.run(&mut world);
+    assert!(world.get::<Sleeping>(body).is_none());
+}
@@ -1,5 +1,7 @@
 pub mod dynamics;
 pub mod broad;
+pub mod interpolation;
+pub mod island;
 pub mod narrow;
 pub mod resolve_contact;
 pub mod transform;
\ No newline at end of file
@@ -3,236 +3,286 @@ use bevy::prelude::*;
 use crate::{
     colliders::{Collider, ColliderBox, ColliderSphere, ColliderType},
     intersect,
-    primitives::*, PhysicsTime,
+    phase::broad::BroadphasePairs,
+    primitives::*,
+    CollisionDetection, PhysicsConfig, PhysicsTime,
 };
 
 // Narrowphase
-pub fn narrowphase_system_static(
-    mut broad_contacts: EventReader<BroadContact>,
-    bodies: Query<(&GlobalTransform, &Body, &ColliderType)>,
+// Pairs with a Ccd body, or every pair when using CollisionDetection::Dynamic, are swept for their
+// time of impact, the rest only test for overlap at the start of the step. With
+// CollisionDetection::Speculative separated pairs closing fast enough to touch this step also get
+// a contact
+pub fn narrowphase_system(
+    config: Res<PhysicsConfig>,
+    broad_contacts: Res<BroadphasePairs>,
+    bodies: Query<(
+        &GlobalTransform,
+        &Body,
+        &ColliderType,
+        Option<&Ccd>,
+        Option<&LockedAxes>,
+    )>,
     spheres: Query<&ColliderSphere>,
     boxes: Query<&ColliderBox>,
     mut contacts: EventWriter<Contact>,
+    pt: Res<PhysicsTime>,
 ) {
-    for pair in broad_contacts.iter() {
-        unsafe {
-            let (trans_a, body_a, shape_a) = bodies.get_unchecked(pair.a).unwrap();
-            let (trans_b, body_b, shape_b) = bodies.get_unchecked(pair.b).unwrap();
+    let sweep_all = config.collision_dection == CollisionDetection::Dynamic;
+    let speculative = config.collision_dection == CollisionDetection::Speculative;
+    for pair in broad_contacts.pairs.iter() {
+        let (trans_a, body_a, shape_a, ccd_a, locks_a) = bodies.get(pair.a).unwrap();
+        let (trans_b, body_b, shape_b, ccd_b, locks_b) = bodies.get(pair.b).unwrap();
+
+        if body_a.has_infinite_mass() && body_b.has_infinite_mass() {
+            continue;
+        }
 
-            if body_a.has_infinite_mass() && body_b.has_infinite_mass() {
-                continue;
+        let locks_a = locks_a.copied().unwrap_or_default();
+        let locks_b = locks_b.copied().unwrap_or_default();
+        let a = (trans_a, body_a, shape_a, locks_a);
+        let b = (trans_b, body_b, shape_b, locks_b);
+        if sweep_all || ccd_a.is_some() || ccd_b.is_some() {
+            // pairs already touching come back with a time of impact of 0, they are resolved like
+            // any other discrete contact. Later impacts keep their time, see ContactResolution
+            if let Some(contact) = sweep_pair(pair, a, b, &spheres, &boxes, pt.time) {
+                contacts.send(contact);
             }
+        } else if !narrowphase_static(pair, a, b, &spheres, &boxes, &mut contacts) && speculative {
+            narrowphase_speculative(pair, a, b, &spheres, &boxes, pt.time, &mut contacts);
+        }
+    }
+}
 
-            match (shape_a, shape_b) {
-                (ColliderType::Sphere, ColliderType::Sphere) => {
-                    let sphere_a = spheres.get_unchecked(pair.a).unwrap();
-                    let sphere_b = spheres.get_unchecked(pair.b).unwrap();
+pub(crate) type PairBody<'a> = (&'a GlobalTransform, &'a Body, &'a ColliderType, LockedAxes);
 
-                    if let Some((local_point_a, local_point_b)) = intersect::sphere_sphere_static(
-                        sphere_a.radius,
-                        sphere_b.radius,
-                        trans_a.translation,
-                        trans_b.translation,
-                    ) {
-                        // calculate normal and separation distance
-                        let normal = (trans_a.translation - trans_b.translation).normalize();
-                        let ab = trans_a.translation - trans_b.translation;
-                        let separation_dist = ab.length() - (sphere_a.radius + sphere_b.radius);
+fn narrowphase_static(
+    pair: &BroadContact,
+    (trans_a, body_a, shape_a, _): PairBody,
+    (trans_b, body_b, shape_b, _): PairBody,
+    spheres: &Query<&ColliderSphere>,
+    boxes: &Query<&ColliderBox>,
+    contacts: &mut EventWriter<Contact>,
+) -> bool {
+    match (shape_a, shape_b) {
+        (ColliderType::Sphere, ColliderType::Sphere) => {
+            let sphere_a = spheres.get(pair.a).unwrap();
+            let sphere_b = spheres.get(pair.b).unwrap();
+
+            if let Some((local_point_a, local_point_b)) = intersect::sphere_sphere_static(
+                sphere_a.radius,
+                sphere_b.radius,
+                trans_a.translation,
+                trans_b.translation,
+            ) {
+                // calculate normal and separation distance
+                let normal = (trans_a.translation - trans_b.translation).normalize();
+                let ab = trans_a.translation - trans_b.translation;
+                let separation_dist = ab.length() - (sphere_a.radius + sphere_b.radius);
 
-                        // create contact
-                        contacts.send(Contact {
-                            entity_a: pair.a,
-                            entity_b: pair.b,
-                            world_point_a: local_point_a,
-                            world_point_b: local_point_b,
-                            local_point_a,
-                            local_point_b,
-                            normal,
-                            separation_dist,
-                            time_of_impact: 0.0,
-                        });
-                    }
-                }
-                (ColliderType::Sphere, ColliderType::Box) => {
-                    let sphere_a = spheres.get_unchecked(pair.a).unwrap();
-                    let box_b = boxes.get_unchecked(pair.b).unwrap();
-                    gjk_intersect(
-                        pair,
-                        sphere_a,
-                        box_b,
-                        trans_a,
-                        trans_b,
-                        body_a,
-                        body_b,
-                        &mut contacts,
-                    );
-                }
-                (ColliderType::Box, ColliderType::Sphere) => {
-                    let box_a = boxes.get_unchecked(pair.a).unwrap();
-                    let sphere_b = spheres.get_unchecked(pair.b).unwrap();
-                    gjk_intersect(
-                        pair,
-                        box_a,
-                        sphere_b,
-                        trans_a,
-                        trans_b,
-                        body_a,
-                        body_b,
-                        &mut contacts,
-                    );
-                }
-                (ColliderType::Box, ColliderType::Box) => {
-                    let box_a = boxes.get_unchecked(pair.a).unwrap();
-                    let box_b = boxes.get_unchecked(pair.b).unwrap();
-                    gjk_intersect(
-                        pair,
-                        box_a,
-                        box_b,
-                        trans_a,
-                        trans_b,
-                        body_a,
-                        body_b,
-                        &mut contacts,
-                    );
-                }
-                (_, _) => todo!(),
+                // create contact
+                contacts.send(Contact {
+                    entity_a: pair.a,
+                    entity_b: pair.b,
+                    world_point_a: local_point_a,
+                    world_point_b: local_point_b,
+                    local_point_a,
+                    local_point_b,
+                    normal,
+                    separation_dist,
+                    time_of_impact: 0.0,
+                });
+                return true;
             }
+            false
+        }
+        (ColliderType::Sphere, ColliderType::Box) => {
+            let sphere_a = spheres.get(pair.a).unwrap();
+            let box_b = boxes.get(pair.b).unwrap();
+            gjk_intersect(
+                pair, sphere_a, box_b, trans_a, trans_b, body_a, body_b, contacts,
+            )
+        }
+        (ColliderType::Box, ColliderType::Sphere) => {
+            let box_a = boxes.get(pair.a).unwrap();
+            let sphere_b = spheres.get(pair.b).unwrap();
+            gjk_intersect(
+                pair, box_a, sphere_b, trans_a, trans_b, body_a, body_b, contacts,
+            )
         }
+        (ColliderType::Box, ColliderType::Box) => {
+            let box_a = boxes.get(pair.a).unwrap();
+            let box_b = boxes.get(pair.b).unwrap();
+            gjk_intersect(
+                pair, box_a, box_b, trans_a, trans_b, body_a, body_b, contacts,
+            )
+        }
+        (_, _) => todo!(),
     }
 }
 
-pub fn narrowphase_system_dynamic(
-    mut broad_contacts: EventReader<BroadContact>,
-    mut manifold_contacts: EventWriter<ManifoldContactEvent>,
-    bodies: Query<(&mut GlobalTransform, &mut Body, &ColliderType)>,
-    spheres: Query<&ColliderSphere>,
-    boxes: Query<&ColliderBox>,
-    mut contacts: EventWriter<Contact>,
-    pt: Res<PhysicsTime>,
+// Separated pairs closing faster than their gap allows this step get a speculative contact, with
+// a positive separation the solver only removes the closing speed that would make them overlap
+fn narrowphase_speculative(
+    pair: &BroadContact,
+    (trans_a, body_a, shape_a, _): PairBody,
+    (trans_b, body_b, shape_b, _): PairBody,
+    spheres: &Query<&ColliderSphere>,
+    boxes: &Query<&ColliderBox>,
+    dt: f32,
+    contacts: &mut EventWriter<Contact>,
 ) {
-    for pair in broad_contacts.iter() {
-        unsafe {
-            let (mut trans_a, mut body_a, shape_a) = bodies.get_unchecked(pair.a).unwrap();
-            let (mut trans_b, mut body_b, shape_b) = bodies.get_unchecked(pair.b).unwrap();
+    let (world_point_a, world_point_b) = match (shape_a, shape_b) {
+        (ColliderType::Sphere, ColliderType::Sphere) => {
+            let sphere_a = spheres.get(pair.a).unwrap();
+            let sphere_b = spheres.get(pair.b).unwrap();
+            let dir = (trans_b.translation - trans_a.translation).normalize_or_zero();
+            (
+                trans_a.translation + dir * sphere_a.radius,
+                trans_b.translation - dir * sphere_b.radius,
+            )
+        }
+        (ColliderType::Sphere, ColliderType::Box) => intersect::gjk_closest_points(
+            spheres.get(pair.a).unwrap(),
+            trans_a,
+            boxes.get(pair.b).unwrap(),
+            trans_b,
+        ),
+        (ColliderType::Box, ColliderType::Sphere) => intersect::gjk_closest_points(
+            boxes.get(pair.a).unwrap(),
+            trans_a,
+            spheres.get(pair.b).unwrap(),
+            trans_b,
+        ),
+        (ColliderType::Box, ColliderType::Box) => intersect::gjk_closest_points(
+            boxes.get(pair.a).unwrap(),
+            trans_a,
+            boxes.get(pair.b).unwrap(),
+            trans_b,
+        ),
+        // no closest points for other shapes yet, they only get the static overlap test
+        (_, _) => return,
+    };
 
-            if body_a.has_infinite_mass() && body_b.has_infinite_mass() {
-                continue;
-            }
+    let ab = world_point_b - world_point_a;
+    let separation_dist = ab.length();
+    if separation_dist <= f32::EPSILON {
+        return;
+    }
+    let normal = ab / separation_dist;
 
-            match (shape_a, shape_b) {
-                (ColliderType::Sphere, ColliderType::Sphere) => {
-                    let sphere_a = spheres.get_unchecked(pair.a).unwrap();
-                    let sphere_b = spheres.get_unchecked(pair.b).unwrap();
+    // closing speed of the closest points along the normal
+    let vel_a = body_a.linear_velocity
+        + body_a
+            .angular_velocity
+            .cross(world_point_a - body_a.centre_of_mass_world(trans_a));
+    let vel_b = body_b.linear_velocity
+        + body_b
+            .angular_velocity
+            .cross(world_point_b - body_b.centre_of_mass_world(trans_b));
+    let closing_speed = (vel_a - vel_b).dot(normal);
+    if closing_speed * dt <= separation_dist {
+        return;
+    }
 
-                    if let Some((world_point_a, world_point_b, time_of_impact)) =
-                        intersect::sphere_sphere_dynamic(
-                            sphere_a.radius,
-                            sphere_b.radius,
-                            trans_a.translation,
-                            trans_b.translation,
-                            body_a.linear_velocity,
-                            body_b.linear_velocity,
-                            pt.time,
-                        )
-                    {
-                        // step bodies forward to get local space collision points
-                        body_a.update(&mut trans_a, time_of_impact);
-                        body_b.update(&mut trans_b, time_of_impact);
+    contacts.send(Contact {
+        entity_a: pair.a,
+        entity_b: pair.b,
+        world_point_a,
+        world_point_b,
+        local_point_a: body_a.world_to_local(trans_a, world_point_a),
+        local_point_b: body_b.world_to_local(trans_b, world_point_b),
+        normal,
+        separation_dist,
+        time_of_impact: 0.0,
+    });
+}
 
-                        // convert world space contacts to local space
-                        let local_point_a = body_a.world_to_local(&trans_a, world_point_a);
-                        let local_point_b = body_b.world_to_local(&trans_b, world_point_b);
+/// Sweeps the pair over dt, returning the first contact with its time of impact from now
+pub(crate) fn sweep_pair(
+    pair: &BroadContact,
+    a: PairBody,
+    b: PairBody,
+    spheres: &Query<&ColliderSphere>,
+    boxes: &Query<&ColliderBox>,
+    dt: f32,
+) -> Option<Contact> {
+    let (trans_a, body_a, shape_a, locks_a) = a;
+    let (trans_b, body_b, shape_b, locks_b) = b;
+    match (shape_a, shape_b) {
+        (ColliderType::Sphere, ColliderType::Sphere) => {
+            let sphere_a = spheres.get(pair.a).unwrap();
+            let sphere_b = spheres.get(pair.b).unwrap();
 
-                        let normal = (trans_a.translation - trans_b.translation).normalize();
+            let (world_point_a, world_point_b, time_of_impact) = intersect::sphere_sphere_dynamic(
+                sphere_a.radius,
+                sphere_b.radius,
+                trans_a.translation,
+                trans_b.translation,
+                body_a.linear_velocity,
+                body_b.linear_velocity,
+                dt,
+            )?;
 
-                        // unwind time step
-                        body_a.update(&mut trans_a, -time_of_impact);
-                        body_b.update(&mut trans_b, -time_of_impact);
+            // get local space collision points at the time of impact
+            let (pos_a, local_point_a) =
+                body_a.local_collision_point(trans_a, time_of_impact, world_point_a, locks_a);
+            let (pos_b, local_point_b) =
+                body_b.local_collision_point(trans_b, time_of_impact, world_point_b, locks_b);
 
-                        // calculate the separation distance
-                        let ab = trans_a.translation - trans_b.translation;
-                        let separation_dist = ab.length() - (sphere_a.radius + sphere_b.radius);
+            let normal = (pos_a - pos_b).normalize();
 
-                        let contact = Contact {
-                            world_point_a,
-                            world_point_b,
-                            local_point_a,
-                            local_point_b,
-                            normal,
-                            separation_dist,
-                            time_of_impact,
-                            entity_a: pair.a,
-                            entity_b: pair.b,
-                        };
+            // calculate the separation distance
+            let ab = trans_a.translation - trans_b.translation;
+            let separation_dist = ab.length() - (sphere_a.radius + sphere_b.radius);
 
-                        send_contact(contact, &mut manifold_contacts, &mut contacts);
-                    }
-                }
-                (ColliderType::Sphere, ColliderType::Box) => {
-                    let collider_a = spheres.get_unchecked(pair.a).unwrap();
-                    let collider_b = boxes.get_unchecked(pair.b).unwrap();
+            Some(Contact {
+                world_point_a,
+                world_point_b,
+                local_point_a,
+                local_point_b,
+                normal,
+                separation_dist,
+                time_of_impact,
+                entity_a: pair.a,
+                entity_b: pair.b,
+            })
+        }
+        (ColliderType::Sphere, ColliderType::Box) => {
+            let collider_a = spheres.get(pair.a).unwrap();
+            let collider_b = boxes.get(pair.b).unwrap();
 
-                    if let Some(contact) = conservative_advancement(
-                        pair,
-                        &mut trans_a,
-                        &mut trans_b,
-                        &mut body_a,
-                        &mut body_b,
-                        collider_a,
-                        collider_b,
-                        pt.time,
-                    ) {
-                        send_contact(contact, &mut manifold_contacts, &mut contacts);
-                    }
-                }
-                (ColliderType::Box, ColliderType::Sphere) => {
-                    let collider_a = boxes.get_unchecked(pair.a).unwrap();
-                    let collider_b = spheres.get_unchecked(pair.b).unwrap();
-                    if let Some(contact) = conservative_advancement(pair,
-                        &mut trans_a,
-                        &mut trans_b,
-                        &mut body_a,
-                        &mut body_b,
-                        collider_a,
-                        collider_b,
-                        pt.time,
-                    ) {
-                        send_contact(contact, &mut manifold_contacts, &mut contacts);
-                    }
-                },
-                (ColliderType::Box, ColliderType::Box) => {
-                    let collider_a = boxes.get_unchecked(pair.a).unwrap();
-                    let collider_b = boxes.get_unchecked(pair.b).unwrap();
+            conservative_advancement(pair, a, b, collider_a, collider_b, dt)
+        }
+        (ColliderType::Box, ColliderType::Sphere) => {
+            let collider_a = boxes.get(pair.a).unwrap();
+            let collider_b = spheres.get(pair.b).unwrap();
+            conservative_advancement(pair, a, b, collider_a, collider_b, dt)
+        }
+        (ColliderType::Box, ColliderType::Box) => {
+            let collider_a = boxes.get(pair.a).unwrap();
+            let collider_b = boxes.get(pair.b).unwrap();
 
-                    if let Some(contact) = conservative_advancement(
-                        pair,
-                        &mut trans_a,
-                        &mut trans_b,
-                        &mut body_a,
-                        &mut body_b,
-                        collider_a,
-                        collider_b,
-                        pt.time,
-                    ) {
-                        send_contact(contact, &mut manifold_contacts, &mut contacts);
-                    }
-                }
-                (_, _) => todo!(),
-            }
+            conservative_advancement(pair, a, b, collider_a, collider_b, dt)
         }
+        (_, _) => todo!(),
     }
 }
 
 fn conservative_advancement(
     pair: &BroadContact,
-    trans_a: &mut GlobalTransform,
-    trans_b: &mut GlobalTransform,
-    body_a: &mut Body,
-    body_b: &mut Body,
+    (trans_a, body_a, _, locks_a): PairBody,
+    (trans_b, body_b, _, locks_b): PairBody,
     collider_a: &impl Collider,
     collider_b: &impl Collider,
     mut dt: f32,
 ) -> Option<Contact> {
+    // advance copies of the bodies, so the real transforms are never touched
+    let mut trans_a = *trans_a;
+    let mut trans_b = *trans_b;
+    let mut body_a = body_a.clone();
+    let mut body_b = body_b.clone();
+
     let mut toi = 0.0;
     let mut num_iters = 0;
     // advance the positions of the bodies until they touch or there's not time left
@@ -240,7 +290,7 @@ fn conservative_advancement(
         // check for intersection
         const BIAS: f32 = 0.001;
         if let Some((mut world_point_a, mut world_point_b)) =
-            intersect::gjk_does_intersect(collider_a, trans_a, collider_b, trans_b, BIAS)
+            intersect::gjk_does_intersect(collider_a, &trans_a, collider_b, &trans_b, BIAS)
         {
             let normal = (world_point_b - world_point_a).normalize_or_zero();
             world_point_a -= normal * BIAS;
@@ -248,31 +298,28 @@ fn conservative_advancement(
             let contact = Contact {
                 world_point_a,
                 world_point_b,
-                local_point_a: body_a.world_to_local(trans_a, world_point_a),
-                local_point_b: body_b.world_to_local(trans_b, world_point_b),
+                local_point_a: body_a.world_to_local(&trans_a, world_point_a),
+                local_point_b: body_b.world_to_local(&trans_b, world_point_b),
                 normal,
                 separation_dist: -(world_point_a - world_point_b).length(),
                 time_of_impact: toi,
                 entity_a: pair.a,
                 entity_b: pair.b,
             };
-            body_a.update(trans_a, -toi);
-            body_b.update(trans_b, -toi);
-
             return Some(contact);
         } else {
             let (world_point_a, world_point_b) =
-                intersect::gjk_closest_points(collider_a, trans_a, collider_b, trans_b);
+                intersect::gjk_closest_points(collider_a, &trans_a, collider_b, &trans_b);
             let contact = Contact {
                 world_point_a,
                 world_point_b,
-                local_point_a: body_a.world_to_local(trans_a, world_point_a),
-                local_point_b: body_b.world_to_local(trans_b, world_point_b),
+                local_point_a: body_a.world_to_local(&trans_a, world_point_a),
+                local_point_b: body_b.world_to_local(&trans_b, world_point_b),
                 normal: Vec3::ZERO,
                 separation_dist: (world_point_a - world_point_b).length(),
                 time_of_impact: 0.0,
                 entity_a: pair.a,
-                entity_b: pair.a,
+                entity_b: pair.b,
             };
 
             // get the vector from the closest point on A to the closest point on B
@@ -283,8 +330,13 @@ fn conservative_advancement(
             let mut ortho_speed = relative_velocity.dot(ab);
 
             // add to the ortho_speed the maximum angular speeds of the relative shapes
-            let angular_speed_a = collider_a.fastest_linear_speed(body_a.angular_velocity, body_a.center_of_mass, ab);
-            let angular_speed_b = collider_b.fastest_linear_speed(body_b.angular_velocity, body_b.center_of_mass, -ab);
+            let angular_speed_a =
+                collider_a.fastest_linear_speed(body_a.angular_velocity, body_a.center_of_mass, ab);
+            let angular_speed_b = collider_b.fastest_linear_speed(
+                body_b.angular_velocity,
+                body_b.center_of_mass,
+                -ab,
+            );
             ortho_speed += angular_speed_a + angular_speed_b;
 
             if ortho_speed <= 0.0 {
@@ -298,8 +350,8 @@ fn conservative_advancement(
 
             dt -= time_to_go;
             toi += time_to_go;
-            body_a.update(trans_a, time_to_go);
-            body_b.update(trans_a, time_to_go);
+            body_a.update(&mut trans_a, time_to_go, locks_a);
+            body_b.update(&mut trans_b, time_to_go, locks_b);
         };
 
         num_iters += 1;
@@ -319,7 +371,7 @@ fn gjk_intersect(
     body_a: &Body,
     body_b: &Body,
     contacts: &mut EventWriter<Contact>,
-) {
+) -> bool {
     const BIAS: f32 = 0.001;
     if let Some((mut world_point_a, mut world_point_b)) =
         intersect::gjk_does_intersect(collider_a, trans_a, collider_b, trans_b, BIAS)
@@ -339,19 +391,7 @@ fn gjk_intersect(
             separation_dist: -(world_point_a - world_point_b).length(),
             time_of_impact: 0.0,
         });
+        return true;
     }
-}
-
-fn send_contact(
-    contact: Contact,
-    manifold_contacts: &mut EventWriter<ManifoldContactEvent>,
-    contacts: &mut EventWriter<Contact>,
-) {
-    if contact.time_of_impact == 0.0 {
-        // static contact
-        manifold_contacts.send(ManifoldContactEvent(contact));
-    } else {
-        // ballistic contact
-        contacts.send(contact);
-    }
+    false
 }
@@ -1,98 +1,304 @@
 use crate::{
+    colliders::{ColliderBox, ColliderSphere, ColliderType},
     constraints::{ConstraintConfig, ConstraintPenetration},
+    phase::{
+        broad::BroadphasePairs,
+        island::{for_each_island, Islands},
+        narrow,
+    },
     primitives::*,
-    PhysicsTime,
+    ContactResolution, PhysicsConfig, PhysicsTime,
 };
 
-use bevy::prelude::*;
+use bevy::{prelude::*, tasks::ComputeTaskPool};
 
 pub fn resolve_contact_system(
     pt: Res<PhysicsTime>,
-    mut query: Query<(&mut Body, &mut GlobalTransform)>,
+    config: Res<PhysicsConfig>,
+    pool: Res<ComputeTaskPool>,
+    islands: Res<Islands>,
+    pairs: Res<BroadphasePairs>,
+    mut query: Bodies,
+    shapes: Query<&ColliderType>,
+    spheres: Query<&ColliderSphere>,
+    boxes: Query<&ColliderBox>,
     mut contacts: EventReader<Contact>,
 ) {
-    for contact in contacts.iter() {
+    if config.contact_resolution == ContactResolution::TimeOfImpact {
+        resolve_contact_ordered(
+            &mut query,
+            &islands,
+            &pairs,
+            (&shapes, &spheres, &boxes),
+            contacts.iter().copied().collect(),
+            pt.time,
+        );
+        return;
+    }
+
+    // kinematic bodies aren't in any island, they only move by the velocity they were given
+    for e in islands.kinematic.iter() {
+        if let Ok((mut body, mut transform, locks)) = query.get_mut(*e) {
+            body.update(&mut transform, pt.time, locks.copied().unwrap_or_default());
+        }
+    }
+
+    let groups = islands.group(contacts.iter(), |contact| {
+        (contact.entity_a, contact.entity_b)
+    });
+    let work = groups
+        .into_iter()
+        .zip(islands.islands.iter())
+        .collect::<Vec<_>>();
+
+    let query = &query;
+    let dt = pt.time;
+    for_each_island(&pool, config.parallel, work, |(contacts, bodies)| {
+        // Safety: islands never share a moving body, bodies shared between islands are only read
         unsafe {
-            let a = query.get_unchecked(contact.entity_a);
-            let b = query.get_unchecked(contact.entity_b);
-            if a.is_err() || b.is_err() {
-                continue;
+            for contact in contacts {
+                resolve_contact_pair(query, contact, dt, resolve_contact_at_start);
             }
 
-            let (mut body_a, mut transform_a) = a.unwrap();
-            let (mut body_b, mut transform_b) = b.unwrap();
-
-            resolve_contact(
-                contact,
-                &mut body_a,
-                &mut transform_a,
-                &mut body_b,
-                &mut transform_b,
-            );
+            // Apply ballistic impulses, static, kinematic and sleeping bodies are never in an island
+            for e in bodies.iter() {
+                if let Ok((mut body, mut transform, locks)) = query.get_unchecked(*e) {
+                    body.update(&mut transform, dt, locks.copied().unwrap_or_default())
+                }
+            }
         }
-    }
+    });
+}
 
-    // Apply ballistic impulses
-    for (mut body, mut transform) in query.iter_mut() {
-        body.update(&mut transform, pt.time)
+// Resolves the contact on copies of the two bodies and only writes back the ones it can push.
+// Bodies with infinite mass can be in contacts from several islands at once, so they are never
+// borrowed mutably.
+//
+// Safety: the caller must be the only one touching the bodies with finite mass
+unsafe fn resolve_contact_pair(query: &Bodies, contact: &Contact, dt: f32, resolve: Resolver) {
+    let copy = |e: Entity| {
+        query.get(e).ok().map(|(body, transform, locks)| {
+            (body.clone(), *transform, locks.copied().unwrap_or_default())
+        })
+    };
+    let ((mut body_a, mut transform_a, locks_a), (mut body_b, mut transform_b, locks_b)) =
+        match (copy(contact.entity_a), copy(contact.entity_b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return,
+        };
+
+    resolve(
+        contact,
+        (&mut body_a, &mut transform_a, locks_a),
+        (&mut body_b, &mut transform_b, locks_b),
+        dt,
+    );
+
+    for (e, body, transform) in [
+        (contact.entity_a, body_a, transform_a),
+        (contact.entity_b, body_b, transform_b),
+    ] {
+        if body.has_infinite_mass() {
+            continue;
+        }
+        if let Ok((mut old_body, mut old_transform, _)) = query.get_unchecked(e) {
+            *old_body = body;
+            *old_transform = transform;
+        }
     }
 }
 
-pub fn resolve_contact_system_ordered(
-    pt: Res<PhysicsTime>,
-    mut query: Query<(&mut Body, &mut GlobalTransform)>,
-    mut contacts: EventReader<Contact>,
+type Bodies<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Body,
+        &'static mut GlobalTransform,
+        Option<&'static LockedAxes>,
+    ),
+>;
+
+// A body a contact is resolved on, with the axes it's locked on
+type ContactBody<'a> = (&'a mut Body, &'a mut GlobalTransform, LockedAxes);
+
+type Resolver = fn(&Contact, ContactBody, ContactBody, f32);
+
+type Colliders<'a, 'w, 's> = (
+    &'a Query<'w, 's, &'static ColliderType>,
+    &'a Query<'w, 's, &'static ColliderSphere>,
+    &'a Query<'w, 's, &'static ColliderBox>,
+);
+
+// Resolves one impact at a time from earliest to latest, every moving body is advanced up to each
+// impact before it is resolved. The impact changes the velocities of both bodies, so every pair
+// they are in is swept again and their old predictions are dropped
+fn resolve_contact_ordered(
+    query: &mut Bodies,
+    islands: &Islands,
+    pairs: &BroadphasePairs,
+    colliders: Colliders,
+    mut list: Vec<Contact>,
+    dt: f32,
 ) {
-    // sort the times of impact from earliest to latest
-    let mut list = contacts.iter().collect::<Vec<_>>();
+    let moving = islands
+        .islands
+        .iter()
+        .flatten()
+        .chain(islands.kinematic.iter())
+        .copied()
+        .collect::<Vec<_>>();
+
+    // a degenerate sweep can give a time that can't be ordered
+    list.retain(|contact| contact.time_of_impact.is_finite());
 
-    list.sort_unstable_by(|a, b| a.time_of_impact.partial_cmp(&b.time_of_impact).unwrap());
+    // two bodies pinned together could keep trading impacts, so cap the work
+    let mut impacts_left = (list.len() + pairs.pairs.len()) * 4;
 
-    // Apply Ballistic impulses
     let mut accumulated_time = 0.0;
-    for contact in list.iter() {
-        let contact_time = contact.time_of_impact - accumulated_time;
+    while impacts_left > 0 && !list.is_empty() {
+        impacts_left -= 1;
+
+        // earliest impact last, so it can be popped
+        list.sort_unstable_by(|a, b| b.time_of_impact.total_cmp(&a.time_of_impact));
+        let contact = list.pop().unwrap();
 
         // position update
-        for (mut body, mut transform) in query.iter_mut() {
-            body.update(&mut transform, contact_time);
-        }
+        let contact_time = contact.time_of_impact - accumulated_time;
+        advance(query, &moving, contact_time);
+        accumulated_time += contact_time;
 
-        unsafe {
-            let (mut body_a, mut transform_a) = query.get_unchecked(contact.entity_a).unwrap();
-            let (mut body_b, mut transform_b) = query.get_unchecked(contact.entity_b).unwrap();
-            resolve_contact(
-                contact,
-                &mut body_a,
-                &mut transform_a,
-                &mut body_b,
-                &mut transform_b,
-            );
+        let (a, b) = (contact.entity_a, contact.entity_b);
+        // Safety: contacts are resolved one at a time
+        unsafe { resolve_contact_pair(query, &contact, dt, resolve_contact) };
+
+        // sweep everything touching the pair again from their new velocities
+        let affected = |e: Entity| (e == a || e == b) && islands.island_of(e).is_some();
+        list.retain(|c| !affected(c.entity_a) && !affected(c.entity_b));
+        for pair in pairs
+            .pairs
+            .iter()
+            .filter(|p| affected(p.a) || affected(p.b))
+        {
+            if let Some(mut next) = sweep(query, colliders, pair, dt - accumulated_time) {
+                // pairs left touching are picked up by the next step's narrowphase, resolving them
+                // here could trade the same impact back and forth
+                if next.time_of_impact > 0.0 {
+                    next.time_of_impact += accumulated_time;
+                    list.push(next);
+                }
+            }
         }
-        accumulated_time += contact_time;
     }
 
     //update positions for the rest of this frame's time
-    let time_remaining = pt.time - accumulated_time;
+    let time_remaining = dt - accumulated_time;
     if time_remaining > 0.0 {
-        for (mut body, mut transform) in query.iter_mut() {
-            body.update(&mut transform, time_remaining)
+        advance(query, &moving, time_remaining);
+    }
+}
+
+fn advance(query: &mut Bodies, moving: &[Entity], dt: f32) {
+    for e in moving.iter() {
+        if let Ok((mut body, mut transform, locks)) = query.get_mut(*e) {
+            body.update(&mut transform, dt, locks.copied().unwrap_or_default());
         }
     }
 }
 
+fn sweep(
+    query: &Bodies,
+    (shapes, spheres, boxes): Colliders,
+    pair: &BroadContact,
+    dt: f32,
+) -> Option<Contact> {
+    let (body_a, trans_a, locks_a) = query.get(pair.a).ok()?;
+    let (body_b, trans_b, locks_b) = query.get(pair.b).ok()?;
+    if body_a.has_infinite_mass() && body_b.has_infinite_mass() {
+        return None;
+    }
+    let shape_a = shapes.get(pair.a).ok()?;
+    let shape_b = shapes.get(pair.b).ok()?;
+
+    narrow::sweep_pair(
+        pair,
+        (
+            trans_a,
+            body_a,
+            shape_a,
+            locks_a.copied().unwrap_or_default(),
+        ),
+        (
+            trans_b,
+            body_b,
+            shape_b,
+            locks_b.copied().unwrap_or_default(),
+        ),
+        spheres,
+        boxes,
+        dt,
+    )
+}
+
+// Islands resolve every contact at the start of the step, so an impact the sweep found later in the
+// step hasn't happened yet. Like a speculative contact it only loses the closing speed that would
+// carry the pair past the impact, it bounces once it's touching on the next step
+fn resolve_contact_at_start(
+    contact: &Contact,
+    (body_a, transform_a, locks_a): ContactBody,
+    (body_b, transform_b, locks_b): ContactBody,
+    dt: f32,
+) {
+    if contact.time_of_impact <= 0.0 {
+        resolve_contact(
+            contact,
+            (body_a, transform_a, locks_a),
+            (body_b, transform_b, locks_b),
+            dt,
+        );
+        return;
+    }
+
+    // the sweeps don't agree on which way the normal faces, so face it the way a closes on b. The
+    // gap along it is what they close before the impact
+    let closing_speed = (body_a.linear_velocity - body_b.linear_velocity).dot(contact.normal);
+    let speculative = Contact {
+        normal: contact.normal * closing_speed.signum(),
+        separation_dist: closing_speed.abs() * contact.time_of_impact,
+        time_of_impact: 0.0,
+        ..*contact
+    };
+    resolve_speculative(
+        &speculative,
+        (body_a, transform_a, locks_a),
+        (body_b, transform_b, locks_b),
+        dt,
+    );
+}
+
 fn resolve_contact(
     contact: &Contact,
-    body_a: &mut Body,
-    transform_a: &mut GlobalTransform,
-    body_b: &mut Body,
-    transform_b: &mut GlobalTransform,
+    (body_a, transform_a, locks_a): ContactBody,
+    (body_b, transform_b, locks_b): ContactBody,
+    dt: f32,
 ) {
+    // speculative contacts are the only ones still apart at the start of the step
+    if contact.time_of_impact == 0.0 && contact.separation_dist > 0.0 {
+        resolve_speculative(
+            contact,
+            (body_a, transform_a, locks_a),
+            (body_b, transform_b, locks_b),
+            dt,
+        );
+        return;
+    }
+
     let elasticity = body_a.elasticity * body_b.elasticity;
-    let total_inv_mass = body_a.inv_mass + body_b.inv_mass;
+    // locked axes change how heavy a body feels in each direction
+    let inv_mass_normal = body_a.inv_mass_along(contact.normal, locks_a)
+        + body_b.inv_mass_along(contact.normal, locks_b);
 
-    let inv_inertia_world_a = body_a.inv_inertia_tensor_world(transform_a);
-    let inv_inertia_world_b = body_b.inv_inertia_tensor_world(transform_b);
+    let inv_inertia_world_a = body_a.inv_inertia_tensor_world(transform_a, locks_a);
+    let inv_inertia_world_b = body_b.inv_inertia_tensor_world(transform_b, locks_b);
 
     let ra = contact.world_point_a - body_a.centre_of_mass_world(transform_a);
     let rb = contact.world_point_b - body_b.centre_of_mass_world(transform_b);
@@ -108,12 +314,15 @@ fn resolve_contact(
     // Calculate the collion impulse
     let vab = vel_a - vel_b;
     let impluse_j =
-        -(1.0 + elasticity) * vab.dot(contact.normal) / (total_inv_mass + angular_factor);
+        -(1.0 + elasticity) * vab.dot(contact.normal) / (inv_mass_normal + angular_factor);
+    // both bodies can be fully locked along the normal
+    let impluse_j = if impluse_j.is_finite() {
+        impluse_j
+    } else {
+        0.0
+    };
     let impluse_vec_j = contact.normal * impluse_j;
 
-    body_a.apply_impulse(contact.world_point_a, impluse_vec_j, transform_a);
-    body_b.apply_impulse(contact.world_point_b, -impluse_vec_j, transform_b);
-
     //
     // Calculate the friction impulse
     //
@@ -131,15 +340,35 @@ fn resolve_contact(
     let inv_inertia = (inertia_a + inertia_b).dot(relative_velocity_tangent);
 
     // calculat the tangential impluse for friction
-    let reduced_mass = 1.0 / (total_inv_mass + inv_inertia);
+    let inv_mass_tangent = body_a.inv_mass_along(relative_velocity_tangent, locks_a)
+        + body_b.inv_mass_along(relative_velocity_tangent, locks_b);
+    let reduced_mass = 1.0 / (inv_mass_tangent + inv_inertia);
     let impluse_friction = velocity_tangent * (reduced_mass * friction);
 
     // TODO: Book didnt have this if check, but I was getitng velocity_tangent of zero leading to
     // a Vec3 Nan when normalized if perfectly lined up on ground
-    if !impluse_friction.is_nan() {
-        // apply kinetic friction
-        body_a.apply_impulse(contact.world_point_a, -impluse_friction, transform_a);
-        body_b.apply_impulse(contact.world_point_b, impluse_friction, transform_b);
+    let impluse_friction = if !impluse_friction.is_finite() {
+        Vec3::ZERO
+    } else {
+        impluse_friction
+    };
+
+    // apply the collision and kinetic friction impulses
+    if !body_a.has_infinite_mass() {
+        body_a.apply_impulse(
+            contact.world_point_a,
+            impluse_vec_j - impluse_friction,
+            transform_a,
+            locks_a,
+        );
+    }
+    if !body_b.has_infinite_mass() {
+        body_b.apply_impulse(
+            contact.world_point_b,
+            impluse_friction - impluse_vec_j,
+            transform_b,
+            locks_b,
+        );
     }
 
     //
@@ -147,13 +376,17 @@ fn resolve_contact(
     //
     if contact.time_of_impact == 0.0 {
         // Let's also move our colliding objects to just outside of each other
-        let a_move_weight = body_a.inv_mass / total_inv_mass;
-        let b_move_weight = body_b.inv_mass / total_inv_mass;
+        let a_move_weight = body_a.inv_mass_along(contact.normal, locks_a) / inv_mass_normal;
+        let b_move_weight = body_b.inv_mass_along(contact.normal, locks_b) / inv_mass_normal;
 
         let direction = contact.world_point_b - contact.world_point_a;
 
-        transform_a.translation += direction * a_move_weight;
-        transform_b.translation -= direction * b_move_weight;
+        if a_move_weight > 0.0 {
+            transform_a.translation += direction * a_move_weight * locks_a.translation_mask();
+        }
+        if b_move_weight > 0.0 {
+            transform_b.translation -= direction * b_move_weight * locks_b.translation_mask();
+        }
     }
 }
 
@@ -274,3 +507,298 @@ pub fn add_manifold_contact_system(
         }
     }
 }
+
+// Speculative contacts are still apart, so only the closing speed that would carry them past
+// each other this step is removed, with no bounce, friction or positional correction
+fn resolve_speculative(
+    contact: &Contact,
+    (body_a, transform_a, locks_a): ContactBody,
+    (body_b, transform_b, locks_b): ContactBody,
+    dt: f32,
+) {
+    // normal points from a to b
+    let normal = contact.normal;
+    let ra = contact.world_point_a - body_a.centre_of_mass_world(transform_a);
+    let rb = contact.world_point_b - body_b.centre_of_mass_world(transform_b);
+
+    let vel_a = body_a.linear_velocity + body_a.angular_velocity.cross(ra);
+    let vel_b = body_b.linear_velocity + body_b.angular_velocity.cross(rb);
+    let closing_speed = (vel_a - vel_b).dot(normal);
+
+    // closing the gap exactly by the end of the step is fine
+    let excess = closing_speed - contact.separation_dist / dt;
+    if excess <= 0.0 {
+        return;
+    }
+
+    let angular_j_a =
+        (body_a.inv_inertia_tensor_world(transform_a, locks_a) * ra.cross(normal)).cross(ra);
+    let angular_j_b =
+        (body_b.inv_inertia_tensor_world(transform_b, locks_b) * rb.cross(normal)).cross(rb);
+    let inv_mass = body_a.inv_mass_along(normal, locks_a)
+        + body_b.inv_mass_along(normal, locks_b)
+        + (angular_j_a + angular_j_b).dot(normal);
+    if inv_mass <= 0.0 {
+        return;
+    }
+    let impulse = normal * (excess / inv_mass);
+
+    if !body_a.has_infinite_mass() {
+        body_a.apply_impulse(contact.world_point_a, -impulse, transform_a, locks_a);
+    }
+    if !body_b.has_infinite_mass() {
+        body_b.apply_impulse(contact.world_point_b, impulse, transform_b, locks_b);
+    }
+}
+
+#[cfg(test)]
+fn test_world(contact_resolution: ContactResolution) -> World {
+    use crate::phase::island;
+    use bevy::{app::Events, tasks::TaskPool};
+
+    let mut world = World::default();
+    world.insert_resource(PhysicsConfig {
+        collision_dection: crate::CollisionDetection::Dynamic,
+        contact_resolution,
+        ..Default::default()
+    });
+    world.insert_resource(PhysicsTime {
+        time: 0.1,
+        step_time: 0.1,
+        ..Default::default()
+    });
+    world.insert_resource(ComputeTaskPool(TaskPool::new()));
+    world.insert_resource(Islands::default());
+    world.insert_resource(island::ConstraintEdges::default());
+    world.insert_resource(BroadphasePairs::default());
+    world.insert_resource(Events::<Contact>::default());
+    world.insert_resource(Events::<ManifoldContactEvent>::default());
+    world
+}
+
+#[cfg(test)]
+fn test_sphere(world: &mut World, x: f32, speed: f32) -> Entity {
+    world
+        .spawn()
+        .insert_bundle((
+            Body {
+                linear_velocity: Vec3::X * speed,
+                elasticity: 1.0,
+                inertia_tensor: Mat3::from_diagonal(Vec3::splat(2.0 * 0.5 * 0.5 / 5.0)),
+                ..Default::default()
+            },
+            ColliderSphere::new(0.5),
+            ColliderType::Sphere,
+            GlobalTransform::from_translation(Vec3::X * x),
+        ))
+        .id()
+}
+
+// thin static wall, its near face is at x - 0.05
+#[cfg(test)]
+fn test_wall(world: &mut World, x: f32) -> Entity {
+    world
+        .spawn()
+        .insert_bundle((
+            Body {
+                body_type: RigidBody::Static,
+                elasticity: 1.0,
+                ..Default::default()
+            },
+            ColliderBox::new_half_vec3(Vec3::new(0.05, 2.0, 2.0)),
+            ColliderType::Box,
+            GlobalTransform::from_translation(Vec3::X * x),
+        ))
+        .id()
+}
+
+#[cfg(test)]
+fn test_step(world: &mut World, pairs: &[(Entity, Entity)]) {
+    use crate::{phase::island, Update};
+
+    world
+        .get_resource_mut::<BroadphasePairs>()
+        .unwrap()
+        .pairs
+        .extend(pairs.iter().map(|(a, b)| BroadContact { a: *a, b: *b }));
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(narrow::narrowphase_system.label(Update::Narrowphase))
+        .add_system(
+            island::build_islands_system
+                .label(Update::Islands)
+                .after(Update::Narrowphase),
+        )
+        .add_system(resolve_contact_system.after(Update::Islands));
+    stage.run(world);
+}
+
+#[test]
+fn test_fast_sphere_hits_thin_wall() {
+    let mut world = test_world(ContactResolution::TimeOfImpact);
+    let sphere = test_sphere(&mut world, 0.0, 100.0);
+    let wall = test_wall(&mut world, 5.0);
+
+    // 10m of travel this step, straight through the wall without a sweep
+    test_step(&mut world, &[(sphere, wall)]);
+
+    // moved to the wall first, 4.45m at t = 0.0445, and back for the rest of the step. The sweep
+    // stops just short of the wall, so allow for its tolerance
+    let trans = world.get::<GlobalTransform>(sphere).unwrap();
+    let body = world.get::<Body>(sphere).unwrap();
+    assert!((trans.translation.x - -1.1).abs() < 0.01);
+    assert!((body.linear_velocity - Vec3::X * -100.0).length() < 1e-3);
+}
+
+#[test]
+fn test_swept_sphere_already_touching_wall() {
+    let mut world = test_world(ContactResolution::Islands);
+    // overlapping the wall's near face at the start of the step, the sweep hits at 0
+    let sphere = test_sphere(&mut world, 4.5, 10.0);
+    let wall = test_wall(&mut world, 5.0);
+
+    test_step(&mut world, &[(sphere, wall)]);
+
+    let body = world.get::<Body>(sphere).unwrap();
+    assert!(body.linear_velocity.x < 0.0);
+}
+
+#[test]
+fn test_speculative_contact_stops_approaching_sphere() {
+    let mut world = test_world(ContactResolution::Islands);
+    world
+        .get_resource_mut::<PhysicsConfig>()
+        .unwrap()
+        .collision_dection = crate::CollisionDetection::Speculative;
+
+    // 1.45m from the wall and closing at 2m a step, only the speed that would carry it past the
+    // wall is removed so it ends the step just touching
+    let sphere = test_sphere(&mut world, 3.0, 20.0);
+    let wall = test_wall(&mut world, 5.0);
+
+    test_step(&mut world, &[(sphere, wall)]);
+
+    let trans = world.get::<GlobalTransform>(sphere).unwrap();
+    let body = world.get::<Body>(sphere).unwrap();
+    assert!((trans.translation.x - 4.45).abs() < 1e-3);
+    assert!((body.linear_velocity.x - 14.5).abs() < 1e-2);
+}
+
+#[test]
+fn test_ordered_multiple_impacts_before_thin_wall() {
+    let mut world = test_world(ContactResolution::TimeOfImpact);
+    let fast = test_sphere(&mut world, 0.0, 100.0);
+    let resting = test_sphere(&mut world, 3.0, 0.0);
+    let wall = test_wall(&mut world, 6.0);
+
+    // the resting sphere is knocked into the wall and bounces back into the fast one, none of
+    // which was predicted at the start of the step
+    test_step(
+        &mut world,
+        &[(fast, resting), (fast, wall), (resting, wall)],
+    );
+
+    let fast_body = world.get::<Body>(fast).unwrap();
+    assert!(fast_body.linear_velocity.x < 0.0);
+
+    let resting_trans = world.get::<GlobalTransform>(resting).unwrap();
+    let resting_body = world.get::<Body>(resting).unwrap();
+    assert!(resting_trans.translation.x + 0.5 <= 5.95 + 0.01);
+    assert!(resting_body.linear_velocity.x.abs() < 1.0);
+}
+
+#[test]
+fn test_parallel_islands_match_serial() {
+    let mut results = Vec::new();
+    for parallel in [true, false] {
+        let mut world = test_world(ContactResolution::Islands);
+        world.get_resource_mut::<PhysicsConfig>().unwrap().parallel = parallel;
+
+        // two islands that both hit the same static wall
+        let left = test_sphere(&mut world, -3.0, 40.0);
+        let right = test_sphere(&mut world, 3.0, -40.0);
+        let wall = test_wall(&mut world, 0.0);
+        test_step(&mut world, &[(left, wall), (right, wall)]);
+        assert_eq!(world.get_resource::<Islands>().unwrap().islands.len(), 2);
+
+        let state = [left, right, wall].map(|e| {
+            let trans = world.get::<GlobalTransform>(e).unwrap();
+            let body = world.get::<Body>(e).unwrap();
+            (
+                trans.translation,
+                trans.rotation,
+                body.linear_velocity,
+                body.angular_velocity,
+            )
+        });
+        results.push(state);
+    }
+
+    assert_eq!(results[0], results[1]);
+    // the wall is only read
+    assert_eq!(results[0][2].0, Vec3::ZERO);
+    assert_eq!(results[0][2].2, Vec3::ZERO);
+}
+
+#[test]
+fn test_kinematic_shared_between_islands() {
+    let mut results = Vec::new();
+    for parallel in [true, false] {
+        let mut world = test_world(ContactResolution::Islands);
+        world.get_resource_mut::<PhysicsConfig>().unwrap().parallel = parallel;
+
+        // a moving kinematic wall hit from both sides, it pushes through both contacts and is
+        // never pushed back
+        let left = test_sphere(&mut world, -3.0, 40.0);
+        let right = test_sphere(&mut world, 3.0, -40.0);
+        let wall = test_wall(&mut world, 0.0);
+        {
+            let mut body = world.get_mut::<Body>(wall).unwrap();
+            body.body_type = RigidBody::Kinematic;
+            body.linear_velocity = Vec3::Y;
+        }
+        test_step(&mut world, &[(left, wall), (right, wall)]);
+        assert_eq!(world.get_resource::<Islands>().unwrap().islands.len(), 2);
+
+        let state = [left, right, wall].map(|e| {
+            let trans = world.get::<GlobalTransform>(e).unwrap();
+            let body = world.get::<Body>(e).unwrap();
+            (trans.translation, body.linear_velocity)
+        });
+        results.push(state);
+    }
+
+    assert_eq!(results[0], results[1]);
+    let (translation, velocity) = results[0][2];
+    assert!((translation - Vec3::Y * 0.1).length() < 1e-6);
+    assert_eq!(velocity, Vec3::Y);
+}
+
+#[test]
+fn test_fast_sphere_stops_short_of_thin_wall() {
+    let mut world = test_world(ContactResolution::Islands);
+    let sphere = test_sphere(&mut world, 0.0, 100.0);
+    let wall = test_wall(&mut world, 5.0);
+
+    // the impact is 4.45m away at t = 0.0445, nothing bounces at the start of the step. Only the
+    // speed that would carry it through is removed, so it ends the step at the wall
+    test_step(&mut world, &[(sphere, wall)]);
+
+    let trans = world.get::<GlobalTransform>(sphere).unwrap();
+    let body = world.get::<Body>(sphere).unwrap();
+    assert!((trans.translation.x - 4.45).abs() < 0.01);
+    assert!((body.linear_velocity.x - 44.5).abs() < 0.1);
+
+    // at the wall it stops or bounces, it never carries on through
+    world
+        .get_resource_mut::<BroadphasePairs>()
+        .unwrap()
+        .pairs
+        .clear();
+    test_step(&mut world, &[(sphere, wall)]);
+    let trans = world.get::<GlobalTransform>(sphere).unwrap();
+    let body = world.get::<Body>(sphere).unwrap();
+    assert!(trans.translation.x < 4.46);
+    assert!(body.linear_velocity.x < 0.01);
+}
@@ -1,18 +1,66 @@
 use bevy::prelude::*;
 
-use crate::primitives::Body;
+use crate::primitives::{Body, Sleeping};
 
-
-// TODO: currently we are just updating local from global, this is worng
-// it breaks all nested transforms, but without a copy of global transform before our systems run
-// not sure how you would do it
+// Physics works on GlobalTransform, so the result is written back into Transform relative to the
+// parent's GlobalTransform, bodies without a parent just copy it
 pub fn update_local_tranform(
-    mut query: Query<(&GlobalTransform, &mut Transform), With<Body>>,
+    mut query: Query<(&Body, &GlobalTransform, &mut Transform, Option<&Parent>), Without<Sleeping>>,
+    parents: Query<&GlobalTransform>,
+) {
+    for (body, gt, mut t, parent) in query.iter_mut() {
+        // static bodies are never moved by physics, writing them would flag them as changed
+        if body.is_static() {
+            continue;
+        }
+
+        let (translation, rotation) = match parent.and_then(|p| parents.get(p.0).ok()) {
+            Some(parent_gt) => {
+                let local = parent_gt.compute_matrix().inverse() * gt.compute_matrix();
+                let (_scale, rotation, translation) = local.to_scale_rotation_translation();
+                (translation, rotation)
+            }
+            None => (gt.translation, gt.rotation),
+        };
+
+        if t.translation != translation || t.rotation != rotation {
+            t.translation = translation;
+            t.rotation = rotation;
+        }
+    }
+}
+
+// Bodies are moved after Bevy's transform propagation has run for the frame, so anything parented
+// to a body that isn't a body itself is brought along here, before rendering
+pub fn propagate_children_system(
+    bodies: Query<(&GlobalTransform, &Children), (With<Body>, Changed<GlobalTransform>)>,
+    mut children: Query<(&Transform, &mut GlobalTransform, Option<&Children>), Without<Body>>,
+) {
+    for (gt, body_children) in bodies.iter() {
+        for child in body_children.iter() {
+            propagate(gt, *child, &mut children);
+        }
+    }
+}
+
+fn propagate(
+    parent: &GlobalTransform,
+    entity: Entity,
+    children: &mut Query<(&Transform, &mut GlobalTransform, Option<&Children>), Without<Body>>,
 ) {
-    for (gt, mut t) in query.iter_mut() {
-        t.translation = gt.translation;
-        t.rotation = gt.rotation;
-        // t.set_rotation_xyz(gt.rotation.x, gt.rotation.y, gt.rotation.z);
-        // t.set_scale(gt.scale);
+    let (gt, next) = match children.get_mut(entity) {
+        Ok((t, mut gt, next)) => {
+            *gt = parent.mul_transform(*t);
+            (
+                *gt,
+                next.map(|next| next.iter().copied().collect::<Vec<_>>()),
+            )
+        }
+        // child bodies are placed by physics, and propagate to their own children
+        Err(_) => return,
+    };
+
+    for child in next.unwrap_or_default() {
+        propagate(&gt, child, children);
     }
 }
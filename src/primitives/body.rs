@@ -1,13 +1,36 @@
 
 use bevy::prelude::*;
 
+use super::LockedAxes;
+
+/// How the simulation moves a body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RigidBody {
+    /// Moved by gravity, forces and contacts
+    Dynamic,
+    /// Never moves
+    Static,
+    /// Only moved by its own velocity or a KinematicTarget, pushes dynamic bodies as if it had
+    /// infinite mass
+    Kinematic,
+}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        RigidBody::Dynamic
+    }
+}
+
 #[derive(Component, Clone)]
 pub struct Body {
+    pub body_type: RigidBody,
     pub linear_velocity: Vec3,
     pub angular_velocity: Vec3,
     pub inv_mass: f32,
     pub elasticity: f32, // min = 0.0, max = 1.0
     pub friction: f32, // min = 0.0, max = 1.0
+    pub linear_damping: f32, // velocity decays by exp(-linear_damping * t), min = 0.0
+    pub angular_damping: f32, // angular velocity decays by exp(-angular_damping * t), min = 0.0
 
     // will be set by collider
     pub center_of_mass: Vec3,
@@ -18,11 +41,14 @@ pub struct Body {
 impl Default for Body {
     fn default() -> Self {
         Self {
+            body_type: RigidBody::Dynamic,
             linear_velocity: Vec3::default(),
             angular_velocity: Vec3::default(),
             inv_mass: 1.0,
             elasticity: 0.5,
             friction: 0.5,
+            linear_damping: 0.0,
+            angular_damping: 0.0,
             center_of_mass: Vec3::default(),
             inertia_tensor: Mat3::default(),
         }
@@ -31,9 +57,35 @@ impl Default for Body {
 
 impl Body {
 
+    /// Static and kinematic bodies, and dynamic bodies with no inverse mass, can't be pushed
     #[inline]
     pub fn has_infinite_mass(&self) -> bool {
-        self.inv_mass == 0.0
+        self.body_type != RigidBody::Dynamic || self.inv_mass == 0.0
+    }
+
+    /// Bodies that never move, a dynamic body with no inverse mass is treated as static
+    #[inline]
+    pub fn is_static(&self) -> bool {
+        match self.body_type {
+            RigidBody::Static => true,
+            RigidBody::Dynamic => self.inv_mass == 0.0,
+            RigidBody::Kinematic => false,
+        }
+    }
+
+    #[inline]
+    pub fn is_kinematic(&self) -> bool {
+        self.body_type == RigidBody::Kinematic
+    }
+
+    /// Inverse mass seen by contacts and constraints, zero for anything that can't be pushed
+    #[inline]
+    pub fn effective_inv_mass(&self) -> f32 {
+        if self.has_infinite_mass() {
+            0.0
+        } else {
+            self.inv_mass
+        }
     }
 
     pub fn centre_of_mass_world(&self, t: &GlobalTransform) -> Vec3 {
@@ -50,48 +102,61 @@ impl Body {
         self.centre_of_mass_world(t) + t.rotation * body_point
     }
 
+    /// Inverse mass felt by an impulse along the unit direction, locked axes can't be pushed
+    pub fn inv_mass_along(&self, dir: Vec3, locks: LockedAxes) -> f32 {
+        self.effective_inv_mass() * (dir * dir).dot(locks.translation_mask())
+    }
+
     pub fn inv_inertia_tensor_local(&self) -> Mat3 {
-        self.inertia_tensor.inverse() * self.inv_mass
+        self.inertia_tensor.inverse() * self.effective_inv_mass()
     }
 
-    pub fn inv_inertia_tensor_world(&self, t: &GlobalTransform) -> Mat3 {
+    pub fn inv_inertia_tensor_world(&self, t: &GlobalTransform, locks: LockedAxes) -> Mat3 {
         let inv_inertia_tensor = self.inv_inertia_tensor_local();
         let orientation = Mat3::from_quat(t.rotation);
-        orientation * inv_inertia_tensor * orientation.transpose()
+        // locked rotation axes get no angular response
+        let locks = Mat3::from_diagonal(locks.rotation_mask());
+        locks * orientation * inv_inertia_tensor * orientation.transpose() * locks
     }
 
-    pub fn apply_impulse(&mut self, impulse_point: Vec3, impulse: Vec3, t: &GlobalTransform) {
-        if self.inv_mass == 0.0 {
+    pub fn apply_impulse(
+        &mut self,
+        impulse_point: Vec3,
+        impulse: Vec3,
+        t: &GlobalTransform,
+        locks: LockedAxes,
+    ) {
+        if self.has_infinite_mass() {
             return;
         }
         // impulse_point is in world space location of the applied impulse
         // impulse is in world space direction and magnitude of the impulse
-        self.apply_impulse_linear(impulse);
+        self.apply_impulse_linear(impulse, locks);
 
         let position = self.centre_of_mass_world(t); // aplying impluses must produce torgues though the center of mass
         let r = impulse_point - position;
         let dl = r.cross(impulse); // this is in world space
-        self.apply_impulse_angular(dl, t);
+        self.apply_impulse_angular(dl, t, locks);
     }
 
-    pub fn apply_impulse_linear(&mut self, impulse: Vec3) {
-        if self.inv_mass == 0.0 {
+    pub fn apply_impulse_linear(&mut self, impulse: Vec3, locks: LockedAxes) {
+        if self.has_infinite_mass() {
             return;
         }
         // p = mv
         // dp = m dv = J
         // => dv = J / m
-        self.linear_velocity += impulse * self.inv_mass;
+        self.linear_velocity += impulse * self.inv_mass * locks.translation_mask();
     }
-    pub fn apply_impulse_angular(&mut self, impulse: Vec3, t: &GlobalTransform) {
-        if self.inv_mass == 0.0 {
+    pub fn apply_impulse_angular(&mut self, impulse: Vec3, t: &GlobalTransform, locks: LockedAxes) {
+        if self.has_infinite_mass() {
             return;
         }
 
         // L = I w = r x p
         // dL = I dw = r x J
         // => dw = I^-1 * (r x J)
-        self.angular_velocity += self.inv_inertia_tensor_world(t) * impulse;
+        self.angular_velocity += self.inv_inertia_tensor_world(t, locks) * impulse;
 
         // clamp angular_velocity - 30 rad/s is fast enough for us
         const MAX_ANGULAR_SPEED: f32 = 30.0;
@@ -101,7 +166,16 @@ impl Body {
         }
     }
 
-    pub fn update(&mut self, transform: &mut GlobalTransform, dt: f32) {
+    /// Exponential decay of the velocities, so the result doesn't depend on the timestep
+    pub fn apply_damping(&mut self, dt: f32) {
+        self.linear_velocity *= (-self.linear_damping * dt).exp();
+        self.angular_velocity *= (-self.angular_damping * dt).exp();
+    }
+
+    pub fn update(&mut self, transform: &mut GlobalTransform, dt: f32, locks: LockedAxes) {
+        // velocities set directly can still point along locked axes
+        self.linear_velocity *= locks.translation_mask();
+
         // apply linear velocity
         transform.translation += self.linear_velocity * dt;
 
@@ -116,13 +190,17 @@ impl Body {
         // T_external = 0 because it was applied in the collision response function
         // T = Ia = w x I * w
         // a = I^-1 (w x I * w)
-        let orientation = Mat3::from_quat(transform.rotation);
-        let inertia_tensor = orientation * self.inertia_tensor * orientation.transpose();
-        let alpha = inertia_tensor.inverse()
-            * (self
-                .angular_velocity
-                .cross(inertia_tensor * self.angular_velocity));
-        self.angular_velocity += alpha * dt;
+        // kinematic bodies keep exactly the velocity they were given
+        if !self.is_kinematic() {
+            let orientation = Mat3::from_quat(transform.rotation);
+            let inertia_tensor = orientation * self.inertia_tensor * orientation.transpose();
+            let alpha = inertia_tensor.inverse()
+                * (self
+                    .angular_velocity
+                    .cross(inertia_tensor * self.angular_velocity));
+            self.angular_velocity += alpha * dt;
+        }
+        self.angular_velocity *= locks.rotation_mask();
 
         // update orientation
         let d_angle = self.angular_velocity * dt;
@@ -144,14 +222,20 @@ impl Body {
     /// This removes that and simulates moving the transform
     /// return translation and local collision point for time of impact
     // TODO: Hate coping this much logic
-    pub fn local_collision_point(&self, transform: &GlobalTransform, toi: f32, world_point: Vec3) -> (Vec3, Vec3) {
+    pub fn local_collision_point(
+        &self,
+        transform: &GlobalTransform,
+        toi: f32,
+        world_point: Vec3,
+        locks: LockedAxes,
+    ) -> (Vec3, Vec3) {
 
          let mut tmp = transform.to_owned();
 
          // Start Update Simulation
 
          // apply linear velocity
-         tmp.translation += self.linear_velocity * toi;
+         tmp.translation += self.linear_velocity * locks.translation_mask() * toi;
 
          // we have an angular velocity around the centre of mass, this needs to be converted to
          // relative body translation. This way we can properly update the rotation of the model
@@ -166,12 +250,17 @@ impl Body {
          // a = I^-1 (w x I * w)
          let orientation = Mat3::from_quat(tmp.rotation);
          let inertia_tensor = orientation * self.inertia_tensor * orientation.transpose();
-         let alpha = inertia_tensor.inverse()
-             * (self
-                 .angular_velocity
-                 .cross(inertia_tensor * self.angular_velocity));
+         let alpha = if self.is_kinematic() {
+             Vec3::ZERO
+         } else {
+             inertia_tensor.inverse()
+                 * (self
+                     .angular_velocity
+                     .cross(inertia_tensor * self.angular_velocity))
+         };
 
-         let tmp_angular_velocity = self.angular_velocity + alpha * toi;
+         let tmp_angular_velocity =
+             (self.angular_velocity + alpha * toi) * locks.rotation_mask();
  
          // update orientation
          let d_angle = tmp_angular_velocity * toi;
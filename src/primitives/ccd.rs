@@ -0,0 +1,7 @@
+use bevy::prelude::*;
+
+/// Sweeps every pair this body is in for its time of impact, so fast bodies like bullets don't
+/// tunnel through others. Pairs without one use the discrete path unless
+/// [`CollisionDetection::Dynamic`](crate::CollisionDetection::Dynamic) is set.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct Ccd;
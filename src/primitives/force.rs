@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+
+/// Space a force or torque vector is given in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForceSpace {
+    World,
+    /// Rotates with the body
+    Local,
+}
+
+impl Default for ForceSpace {
+    fn default() -> Self {
+        ForceSpace::World
+    }
+}
+
+/// Force in newtons the dynamics phase applies to the body every physics step, alongside gravity.
+/// One-shot forces are removed at the end of the step they were applied in.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ExternalForce {
+    pub force: Vec3,
+    pub space: ForceSpace,
+    /// Point in body space, relative to the centre of mass, None pushes through the centre of mass
+    pub point: Option<Vec3>,
+    pub persistent: bool,
+}
+
+impl Default for ExternalForce {
+    fn default() -> Self {
+        Self {
+            force: Vec3::ZERO,
+            space: ForceSpace::World,
+            point: None,
+            persistent: true,
+        }
+    }
+}
+
+impl ExternalForce {
+    pub fn new(force: Vec3) -> Self {
+        Self {
+            force,
+            ..Default::default()
+        }
+    }
+
+    pub fn local(mut self) -> Self {
+        self.space = ForceSpace::Local;
+        self
+    }
+
+    pub fn at_point(mut self, point: Vec3) -> Self {
+        self.point = Some(point);
+        self
+    }
+
+    pub fn one_shot(mut self) -> Self {
+        self.persistent = false;
+        self
+    }
+
+    /// Force in world space for a body with the given rotation
+    pub fn world_force(&self, rotation: Quat) -> Vec3 {
+        match self.space {
+            ForceSpace::World => self.force,
+            ForceSpace::Local => rotation * self.force,
+        }
+    }
+}
+
+/// Torque in newton metres the dynamics phase applies to the body every physics step.
+/// One-shot torques are removed at the end of the step they were applied in.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ExternalTorque {
+    pub torque: Vec3,
+    pub space: ForceSpace,
+    pub persistent: bool,
+}
+
+impl Default for ExternalTorque {
+    fn default() -> Self {
+        Self {
+            torque: Vec3::ZERO,
+            space: ForceSpace::World,
+            persistent: true,
+        }
+    }
+}
+
+impl ExternalTorque {
+    pub fn new(torque: Vec3) -> Self {
+        Self {
+            torque,
+            ..Default::default()
+        }
+    }
+
+    pub fn local(mut self) -> Self {
+        self.space = ForceSpace::Local;
+        self
+    }
+
+    pub fn one_shot(mut self) -> Self {
+        self.persistent = false;
+        self
+    }
+
+    /// Torque in world space for a body with the given rotation
+    pub fn world_torque(&self, rotation: Quat) -> Vec3 {
+        match self.space {
+            ForceSpace::World => self.torque,
+            ForceSpace::Local => rotation * self.torque,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+
+/// Scales every source of gravity acting on the body, 0.0 turns gravity off for it
+#[derive(Component, Debug, Clone, Copy)]
+pub struct GravityScale(pub f32);
+
+impl Default for GravityScale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Extra gravity on top of PhysicsConfig::gravity, placed with the entity's GlobalTransform.
+/// Every field reaching a body is summed.
+#[derive(Component, Debug, Clone, Copy)]
+pub enum GravityField {
+    /// Pulls towards the entity, `strength` in m/s^2 at `radius` and falling off with the inverse
+    /// square of the distance past it. Nothing is felt beyond `max_distance`.
+    Point {
+        strength: f32,
+        radius: f32,
+        max_distance: f32,
+    },
+    /// Constant acceleration in world space inside a box around the entity, the box rotates with
+    /// it. An acceleration opposing the global gravity makes a low gravity room.
+    Directional {
+        acceleration: Vec3,
+        half_extents: Vec3,
+    },
+}
+
+impl GravityField {
+    /// Acceleration the field gives a body at the world position
+    pub fn acceleration(&self, transform: &GlobalTransform, position: Vec3) -> Vec3 {
+        match *self {
+            GravityField::Point {
+                strength,
+                radius,
+                max_distance,
+            } => {
+                let delta = transform.translation - position;
+                let distance = delta.length();
+                if distance > max_distance || distance <= f32::EPSILON {
+                    return Vec3::ZERO;
+                }
+                let falloff = (radius / distance.max(radius)).powi(2);
+                delta / distance * strength * falloff
+            }
+            GravityField::Directional {
+                acceleration,
+                half_extents,
+            } => {
+                let local = transform.rotation.conjugate() * (position - transform.translation);
+                if local.abs().cmple(half_extents).all() {
+                    acceleration
+                } else {
+                    Vec3::ZERO
+                }
+            }
+        }
+    }
+}
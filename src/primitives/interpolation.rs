@@ -0,0 +1,26 @@
+use bevy::prelude::*;
+
+/// Add to a body to blend its rendered pose between the last two physics steps, smoothing out
+/// motion when using a fixed timestep
+#[derive(Component, Default, Debug, Clone, Copy)]
+pub struct PhysicsInterpolation {
+    pub(crate) previous_translation: Vec3,
+    pub(crate) previous_rotation: Quat,
+    pub(crate) current_translation: Vec3,
+    pub(crate) current_rotation: Quat,
+    // Transform written back at the end of the last step, relative to the parent if there is one
+    pub(crate) local_translation: Vec3,
+    pub(crate) local_rotation: Quat,
+}
+
+impl PhysicsInterpolation {
+    /// Translation and rotation at the start of the last physics step
+    pub fn previous(&self) -> (Vec3, Quat) {
+        (self.previous_translation, self.previous_rotation)
+    }
+
+    /// Translation and rotation at the end of the last physics step
+    pub fn current(&self) -> (Vec3, Quat) {
+        (self.current_translation, self.current_rotation)
+    }
+}
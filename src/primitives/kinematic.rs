@@ -0,0 +1,18 @@
+use bevy::prelude::*;
+
+/// Pose a kinematic body should reach by the end of the next physics step, its velocities are set
+/// each step to get it there. Remove it to drive the body by its velocities again.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct KinematicTarget {
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+impl KinematicTarget {
+    pub fn new(translation: Vec3, rotation: Quat) -> Self {
+        Self {
+            translation,
+            rotation,
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use bevy::prelude::*;
+
+/// Locks world space axes of a body, locked translations and rotations are never changed by
+/// gravity, forces, contacts or constraints
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+pub struct LockedAxes {
+    pub translation: BVec3,
+    pub rotation: BVec3,
+}
+
+impl LockedAxes {
+    /// Keeps the body on the XY plane, only spinning around Z, for side-scrollers
+    pub fn xy_plane() -> Self {
+        Self {
+            translation: BVec3::new(false, false, true),
+            rotation: BVec3::new(true, true, false),
+        }
+    }
+
+    /// Stops the body tipping over, only turning around Y
+    pub fn upright() -> Self {
+        Self {
+            translation: BVec3::new(false, false, false),
+            rotation: BVec3::new(true, false, true),
+        }
+    }
+
+    /// 0.0 for locked translation axes, 1.0 for free ones
+    pub fn translation_mask(&self) -> Vec3 {
+        Vec3::select(self.translation, Vec3::ZERO, Vec3::ONE)
+    }
+
+    /// 0.0 for locked rotation axes, 1.0 for free ones
+    pub fn rotation_mask(&self) -> Vec3 {
+        Vec3::select(self.rotation, Vec3::ZERO, Vec3::ONE)
+    }
+}
@@ -1,9 +1,23 @@
 mod body;
 mod bound;
+mod ccd;
 mod contact;
+mod force;
+mod gravity;
+mod interpolation;
+mod kinematic;
+mod locked_axes;
 mod manifold;
+mod sleep;
 
 pub use body::*;
 pub use bound::*;
+pub use ccd::*;
 pub use contact::*;
+pub use force::*;
+pub use gravity::*;
+pub use interpolation::*;
+pub use kinematic::*;
+pub use locked_axes::*;
 pub use manifold::*;
+pub use sleep::*;
@@ -0,0 +1,13 @@
+use bevy::prelude::*;
+
+/// Marks a body that has come to rest with the rest of its island, it is skipped by the simulation
+/// until a contact, impulse or move wakes it. Remove it to wake the body yourself.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Sleeping {
+    pub(crate) translation: Vec3,
+    pub(crate) rotation: Quat,
+}
+
+/// How long a body has been resting for
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct SleepTimer(pub(crate) f32);